@@ -0,0 +1,269 @@
+//! Derive macros for fallblock's packet types.
+//!
+//! `#[derive(Packet)]` implements `crate::protocol::Packet` for an enum where every variant
+//! is tagged with `#[packet(id = ...)]`, or for a struct tagged the same way.
+//! `#[derive(PacketField)]` implements `crate::io::PacketField` for a struct, so that it can
+//! be nested inside a packet.
+//!
+//! Fields are read and written in declaration order using their `PacketField` impl, unless
+//! one of these attributes picks a different encoding:
+//!
+//! - `#[var_int]` / `#[var_long]` for `i32` / `i64`
+//! - `#[string(max = 16)]` for a `String` with a length limit other than 32767
+//! - `#[remaining]` for a `Vec<u8>` holding the rest of the packet
+//! - `#[json]` for any serde type, encoded as a JSON string
+//! - `#[nbt]` for any serde type, encoded as NBT
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Expr, Fields,
+    LitInt, Result,
+};
+
+const FIELD_ATTRIBUTES: &[&str] = &["var_int", "var_long", "string", "remaining", "json", "nbt"];
+
+#[proc_macro_derive(Packet, attributes(packet, var_int, var_long, string, remaining, json, nbt))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_packet(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(PacketField, attributes(var_int, var_long, string, remaining, json, nbt))]
+pub fn derive_packet_field(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_packet_field(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_packet(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (packet_id, read, write) = match &input.data {
+        Data::Enum(data) => {
+            let mut ids = Vec::new();
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            for variant in &data.variants {
+                let id = packet_id(&variant.attrs, variant.span())?;
+                let ident = &variant.ident;
+                let path = quote!(Self::#ident);
+                let (pattern, read_fields, write_fields) = fields(&path, &variant.fields)?;
+                let wildcard = match &variant.fields {
+                    Fields::Named(_) => quote!(#path { .. }),
+                    Fields::Unnamed(_) => quote!(#path(..)),
+                    Fields::Unit => quote!(#path),
+                };
+                ids.push(quote!(#wildcard => #id));
+                reads.push(quote!(#id => Ok(Some(#read_fields))));
+                writes.push(quote!(#pattern => { #write_fields }));
+            }
+            (
+                quote! {
+                    match self {
+                        #(#ids,)*
+                    }
+                },
+                quote! {
+                    match packet_id {
+                        #(#reads,)*
+                        _ => Ok(None),
+                    }
+                },
+                quote! {
+                    match self {
+                        #(#writes)*
+                    }
+                },
+            )
+        }
+        Data::Struct(data) => {
+            let id = packet_id(&input.attrs, input.ident.span())?;
+            let (pattern, read_fields, write_fields) = fields(&quote!(Self), &data.fields)?;
+            (
+                quote!(#id),
+                quote! {
+                    if packet_id != #id {
+                        return Ok(None);
+                    }
+                    Ok(Some(#read_fields))
+                },
+                quote! {
+                    let #pattern = self;
+                    #write_fields
+                },
+            )
+        }
+        Data::Union(_) => return Err(Error::new(input.span(), "packets cannot be unions")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics crate::protocol::Packet for #name #ty_generics #where_clause {
+            fn packet_id(&self) -> i32 {
+                #packet_id
+            }
+
+            fn read<R: crate::io::PacketReader>(packet_id: i32, rdr: &mut R) -> crate::util::Result<Option<Self>> {
+                #read
+            }
+
+            #[allow(unused_variables)]
+            fn write_data<W: crate::io::PacketWriter>(&self, wr: &mut W) -> crate::util::Result<()> {
+                #write
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_packet_field(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => return Err(Error::new(input.span(), "PacketField can only be derived for structs")),
+    };
+    let (pattern, read_fields, write_fields) = fields(&quote!(Self), &data.fields)?;
+
+    Ok(quote! {
+        impl #impl_generics crate::io::PacketField for #name #ty_generics #where_clause {
+            fn read_field<R: crate::io::PacketReader>(rdr: &mut R) -> crate::util::Result<Self> {
+                Ok(#read_fields)
+            }
+
+            #[allow(unused_variables)]
+            fn write_field<W: crate::io::PacketWriter>(&self, wr: &mut W) -> crate::util::Result<()> {
+                let #pattern = self;
+                #write_fields
+                Ok(())
+            }
+        }
+    })
+}
+
+fn packet_id(attrs: &[Attribute], span: Span) -> Result<Expr> {
+    let mut id = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("packet")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown packet attribute"))
+            }
+        })?;
+    }
+    id.ok_or_else(|| Error::new(span, "missing #[packet(id = ...)]"))
+}
+
+/// Builds the destructuring pattern, constructor expression and write statements for a set of fields.
+/// Fields in the constructor are read in declaration order, as struct expressions are evaluated left to right.
+fn fields(path: &TokenStream2, fields: &Fields) -> Result<(TokenStream2, TokenStream2, TokenStream2)> {
+    let mut bindings = Vec::new();
+    let mut reads = Vec::new();
+    let mut writes = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let binding = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("field_{}", i),
+        };
+        let encoding = Encoding::from_attrs(&field.attrs)?;
+        reads.push(encoding.read(&field.ty));
+        writes.push(encoding.write(&binding));
+        bindings.push(binding);
+    }
+
+    let names = fields.iter().filter_map(|f| f.ident.as_ref());
+    Ok(match fields {
+        Fields::Named(_) => (
+            quote!(#path { #(#bindings),* }),
+            quote!(#path { #(#names: #reads),* }),
+            quote!(#(#writes)*),
+        ),
+        Fields::Unnamed(_) => (
+            quote!(#path(#(#bindings),*)),
+            quote!(#path(#(#reads),*)),
+            quote!(#(#writes)*),
+        ),
+        Fields::Unit => (quote!(#path), quote!(#path), quote!()),
+    })
+}
+
+enum Encoding {
+    Field,
+    VarInt,
+    VarLong,
+    String(LitInt),
+    Remaining,
+    Json,
+    Nbt,
+}
+
+impl Encoding {
+    fn from_attrs(attrs: &[Attribute]) -> Result<Self> {
+        let mut encoding = None;
+        for attr in attrs {
+            let path = attr.path();
+            if !FIELD_ATTRIBUTES.iter().any(|a| path.is_ident(a)) {
+                continue;
+            }
+            if encoding.is_some() {
+                return Err(Error::new(attr.span(), "a field can only have one encoding attribute"));
+            }
+            encoding = Some(if path.is_ident("var_int") {
+                Self::VarInt
+            } else if path.is_ident("var_long") {
+                Self::VarLong
+            } else if path.is_ident("remaining") {
+                Self::Remaining
+            } else if path.is_ident("json") {
+                Self::Json
+            } else if path.is_ident("nbt") {
+                Self::Nbt
+            } else {
+                let mut max = None;
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("max") {
+                        max = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("unknown string attribute"))
+                    }
+                })?;
+                Self::String(max.ok_or_else(|| Error::new(attr.span(), "missing #[string(max = ...)]"))?)
+            });
+        }
+        Ok(encoding.unwrap_or(Self::Field))
+    }
+
+    fn read(&self, ty: &syn::Type) -> TokenStream2 {
+        match self {
+            Self::Field => quote!(<#ty as crate::io::PacketField>::read_field(rdr)?),
+            Self::VarInt => quote!(crate::io::PacketReader::read_var_int(rdr)?),
+            Self::VarLong => quote!(crate::io::PacketReader::read_var_long(rdr)?),
+            Self::String(max) => quote!(crate::io::PacketReader::read_string(rdr, #max)?),
+            Self::Remaining => quote!(crate::io::PacketReader::read_remaining(rdr)?),
+            Self::Json => quote!(crate::io::PacketReader::read_json(rdr)?),
+            Self::Nbt => quote!(crate::io::PacketReader::read_nbt(rdr)?),
+        }
+    }
+
+    fn write(&self, binding: &syn::Ident) -> TokenStream2 {
+        match self {
+            Self::Field => quote!(crate::io::PacketField::write_field(#binding, wr)?;),
+            Self::VarInt => quote!(crate::io::PacketWriter::write_var_int(wr, *#binding)?;),
+            Self::VarLong => quote!(crate::io::PacketWriter::write_var_long(wr, *#binding)?;),
+            Self::String(max) => quote!(crate::io::PacketWriter::write_string(wr, #binding, #max)?;),
+            Self::Remaining => quote!(crate::io::PacketWriter::write_bytes(wr, #binding)?;),
+            Self::Json => quote!(crate::io::PacketWriter::write_json(wr, #binding)?;),
+            Self::Nbt => quote!(crate::io::PacketWriter::write_nbt(wr, #binding)?;),
+        }
+    }
+}
@@ -1,3 +0,0 @@
-#!/bin/bash
-
-echo "data:image/png;base64,`cat $1 | base64 -w 0`"
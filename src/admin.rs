@@ -0,0 +1,359 @@
+use std::{convert::Infallible, net::{IpAddr, SocketAddr}, sync::Arc, time::UNIX_EPOCH};
+
+use hyper::{
+    body::HttpBody,
+    http::uri::Authority,
+    header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use mc_chat::{ChatComponent, ComponentStyle};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    announcements::TitleConfig,
+    protocol::play::{ChatPosition, OutgoingPlayPacket},
+    store::{ServerStore, Session, SessionMessage},
+    util,
+};
+
+/// A local HTTP API for managing the server, ie. from deploy scripts
+#[derive(Clone, Debug, Deserialize)]
+pub struct AdminConfig {
+    /// Should usually be a loopback address, since anyone who can reach it can kick players
+    pub address: SocketAddr,
+    /// When set, requests need an `Authorization: Bearer <token>` header
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Requests are all small JSON objects, so anything bigger than this is refused rather than read into memory
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+struct SessionInfo {
+    uuid: Uuid,
+    username: String,
+    address: IpAddr,
+    protocol_version: i32,
+    /// Unix timestamp, in seconds
+    joined_at: u64,
+    latency: i32,
+    profile_id: usize,
+    queue_position: Option<usize>,
+}
+
+impl SessionInfo {
+    fn new(store: &ServerStore, session: Session) -> Self {
+        Self {
+            uuid: session.player.uuid,
+            queue_position: store.queue_position(session.player.uuid),
+            username: session.player.username,
+            address: session.connection.address,
+            protocol_version: session.connection.protocol_version,
+            joined_at: session.joined_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            latency: session.player.latency,
+            profile_id: session.player.profile_id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KickRequest {
+    #[serde(default)]
+    message: Option<ChatComponent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BroadcastRequest {
+    #[serde(default)]
+    chat: Option<ChatComponent>,
+    #[serde(default)]
+    title: Option<TitleConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MaintenanceState {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseRequest {
+    /// Everyone in the queue is released if this isn't given
+    #[serde(default)]
+    count: Option<usize>,
+}
+
+/// Serves the admin API on the configured address until the process exits
+pub async fn serve(store: ServerStore, config: AdminConfig) {
+    let token = config.token.map(Arc::from);
+    let make_service = make_service_fn(move |_| {
+        let store = store.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let store = store.clone();
+                let token = token.clone();
+                async move { Ok::<_, Infallible>(handle_request(store, token.as_deref(), request).await) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&config.address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("failed to start admin API on {}: {}", config.address, e);
+            return;
+        }
+    };
+    info!("Serving admin API on http://{}", config.address);
+    if let Err(e) = server.await {
+        error!("admin API failed: {}", e);
+    }
+}
+
+async fn handle_request(store: ServerStore, token: Option<&str>, request: Request<Body>) -> Response<Body> {
+    if let Err((status, message)) = check_headers(request.headers()) {
+        return error(status, message);
+    }
+    if let Some(token) = token {
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| util::constant_time_eq(value.as_bytes(), token.as_bytes()));
+        if !authorized {
+            return error(StatusCode::UNAUTHORIZED, "missing or invalid token");
+        }
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let segments: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let result = match (&method, segments.as_slice()) {
+        (&Method::GET, ["sessions"]) => {
+            let sessions: Vec<_> = store.get_sessions().into_iter().map(|s| SessionInfo::new(&store, s)).collect();
+            Ok(json(StatusCode::OK, &sessions))
+        }
+        (&Method::POST, ["sessions", player, "kick"]) => {
+            read_json::<KickRequest>(request).await.map(|kick| kick_player(&store, player, kick))
+        }
+        (&Method::POST, ["broadcast"]) => read_json::<BroadcastRequest>(request).await.map(|b| broadcast(&store, b)),
+        (&Method::GET, ["maintenance"]) => Ok(json(StatusCode::OK, &MaintenanceState { enabled: store.is_maintenance() })),
+        (&Method::PUT | &Method::POST, ["maintenance"]) => {
+            read_json::<MaintenanceState>(request).await.map(|state| {
+                info!(enabled = state.enabled, "maintenance mode changed through the admin API");
+                store.set_maintenance(state.enabled);
+                json(StatusCode::OK, &state)
+            })
+        }
+        (&Method::POST, ["reload"]) => Ok(reload(store).await),
+        (&Method::POST, ["queue", "release"]) => read_json::<ReleaseRequest>(request).await.map(|release| {
+            let released = store.release_queued(release.count.unwrap_or_else(|| store.queue_len()));
+            info!("force released {} queued players through the admin API", released);
+            json(StatusCode::OK, &serde_json::json!({ "released": released }))
+        }),
+        _ => Err(error(StatusCode::NOT_FOUND, "not found")),
+    };
+    result.unwrap_or_else(|response| response)
+}
+
+fn kick_player(store: &ServerStore, player: &str, kick: KickRequest) -> Response<Body> {
+    let session = match store.find_session(player) {
+        Some(session) => session,
+        None => return error(StatusCode::NOT_FOUND, "player isn't online"),
+    };
+    let message = kick
+        .message
+        .unwrap_or_else(|| ChatComponent::from_text("You were kicked from the server", ComponentStyle::v1_16()));
+    info!(uuid = %session.player.uuid, username = %session.player.username, "kicking player through the admin API");
+    if session.send(SessionMessage::Disconnect(Box::new(message))) {
+        Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).expect("valid response")
+    } else {
+        error(StatusCode::SERVICE_UNAVAILABLE, "player's connection isn't responding")
+    }
+}
+
+fn broadcast(store: &ServerStore, request: BroadcastRequest) -> Response<Body> {
+    let mut packets = Vec::new();
+    if let Some(message) = request.chat {
+        packets.push(OutgoingPlayPacket::ChatMessage {
+            message,
+            position: ChatPosition::System,
+            sender: Uuid::nil(),
+        });
+    }
+    if let Some(title) = &request.title {
+        packets.extend(title.packets(ChatComponent::clone));
+    }
+    if packets.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "nothing to broadcast, set chat and/or title");
+    }
+
+    // The rest of the packets go to the same players, unless their connections are very far behind
+    let mut sent_to = 0;
+    for (i, packet) in packets.into_iter().enumerate() {
+        let sent = store.broadcast(SessionMessage::Packet(Box::new(packet)));
+        if i == 0 {
+            sent_to = sent;
+        }
+    }
+    json(StatusCode::OK, &serde_json::json!({ "sent_to": sent_to }))
+}
+
+async fn reload(store: ServerStore) -> Response<Body> {
+    match tokio::task::spawn_blocking(move || store.reload_config()).await {
+        Ok(Ok(restart_required)) => {
+            info!("reloaded config through the admin API");
+            json(StatusCode::OK, &serde_json::json!({ "restart_required": restart_required }))
+        }
+        Ok(Err(e)) => {
+            warn!("failed to reload config: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+        }
+        Err(e) => {
+            error!("config reload panicked: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "config reload failed")
+        }
+    }
+}
+
+/// Turns away anything a web browser could send on behalf of a page the operator has open,
+/// since the token is optional and the API is usually only protected by listening on loopback.
+/// Browsers add an `Origin` to cross-origin requests and can't set the content type to JSON without one,
+/// and a page using DNS rebinding to look same-origin still has its own hostname in `Host`.
+fn check_headers(headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    if headers.contains_key(ORIGIN) {
+        return Err((StatusCode::FORBIDDEN, "requests from web pages aren't allowed"));
+    }
+    if let Some(host) = headers.get(HOST) {
+        let host = host.to_str().ok().and_then(|host| host.parse::<Authority>().ok());
+        let allowed = host.is_some_and(|host| {
+            let host = host.host().trim_start_matches('[').trim_end_matches(']');
+            host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok()
+        });
+        if !allowed {
+            return Err((StatusCode::FORBIDDEN, "the Host header must be an IP address or localhost"));
+        }
+    }
+    if headers.contains_key(CONTENT_TYPE) && !is_json(headers) {
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, "request bodies must be application/json"));
+    }
+    Ok(())
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Parses the request body, treating an empty body as an empty object
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, Response<Body>> {
+    let json = is_json(request.headers());
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(error(StatusCode::PAYLOAD_TOO_LARGE, "request body is too large"));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let bytes: &[u8] = if bytes.iter().all(u8::is_ascii_whitespace) {
+        b"{}"
+    } else if !json {
+        return Err(error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "request bodies must be application/json"));
+    } else {
+        &bytes
+    };
+    serde_json::from_slice(bytes).map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let body = serde_json::to_vec(value).expect("failed to serialize response");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("valid response")
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{CONTENT_TYPE, HOST, ORIGIN},
+        Body, Request, StatusCode,
+    };
+
+    use super::{check_headers, read_json, MaintenanceState, ReleaseRequest};
+
+    fn rejected_with(request: Request<()>) -> Option<StatusCode> {
+        check_headers(request.headers()).err().map(|(status, _)| status)
+    }
+
+    #[test]
+    fn allows_local_requests() {
+        let request = Request::post("/reload").header(HOST, "127.0.0.1:9101").body(()).unwrap();
+        assert_eq!(rejected_with(request), None);
+        let request = Request::post("/maintenance")
+            .header(HOST, "[::1]:9101")
+            .header(CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(())
+            .unwrap();
+        assert_eq!(rejected_with(request), None);
+        let request = Request::get("/sessions").header(HOST, "localhost:9101").body(()).unwrap();
+        assert_eq!(rejected_with(request), None);
+    }
+
+    #[test]
+    fn rejects_requests_with_an_origin() {
+        let request = Request::post("/reload")
+            .header(HOST, "127.0.0.1:9101")
+            .header(ORIGIN, "https://example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(rejected_with(request), Some(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        let request = Request::get("/sessions").header(HOST, "rebind.example.com:9101").body(()).unwrap();
+        assert_eq!(rejected_with(request), Some(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn rejects_other_content_types() {
+        let request = Request::post("/maintenance")
+            .header(HOST, "127.0.0.1:9101")
+            .header(CONTENT_TYPE, "text/plain")
+            .body(())
+            .unwrap();
+        assert_eq!(rejected_with(request), Some(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+
+    #[tokio::test]
+    async fn only_reads_json_bodies() {
+        let request = Request::post("/maintenance").body(Body::from(r#"{"enabled":true}"#)).unwrap();
+        let response = read_json::<MaintenanceState>(request).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let request = Request::post("/maintenance")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"enabled":true}"#))
+            .unwrap();
+        assert!(read_json::<MaintenanceState>(request).await.unwrap().enabled);
+
+        // Empty bodies don't need a content type, so that ie. `curl -X POST` works
+        let request = Request::post("/queue/release").body(Body::empty()).unwrap();
+        assert_eq!(read_json::<ReleaseRequest>(request).await.unwrap().count, None);
+    }
+}
@@ -0,0 +1,71 @@
+use mc_chat::{ChatComponent, ComponentStyle};
+use serde::Deserialize;
+
+use crate::protocol::play::OutgoingPlayPacket;
+
+/// A message sent to each player some time after they join, and optionally repeated.
+/// Text can use the usual placeholders, along with `{username}`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AnnouncementConfig {
+    /// Seconds after joining before it's first sent
+    #[serde(default)]
+    pub delay: u64,
+    /// Seconds between repeats, or `None` to only send it once
+    #[serde(default)]
+    pub repeat: Option<u64>,
+    /// Sent as a system chat message
+    #[serde(default)]
+    pub chat: Option<ChatComponent>,
+    #[serde(default)]
+    pub title: Option<TitleConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TitleConfig {
+    #[serde(default)]
+    pub title: Option<ChatComponent>,
+    #[serde(default)]
+    pub subtitle: Option<ChatComponent>,
+    /// In ticks, with the same defaults as vanilla
+    #[serde(default = "default_fade_in")]
+    pub fade_in: i32,
+    #[serde(default = "default_stay")]
+    pub stay: i32,
+    #[serde(default = "default_fade_out")]
+    pub fade_out: i32,
+}
+
+impl TitleConfig {
+    /// Builds the packets to show the title, passing its text through `apply` first
+    pub fn packets(&self, apply: impl Fn(&ChatComponent) -> ChatComponent) -> Vec<OutgoingPlayPacket> {
+        let mut packets = vec![OutgoingPlayPacket::SetTitleTimes {
+            fade_in: self.fade_in,
+            stay: self.stay,
+            fade_out: self.fade_out,
+        }];
+        if let Some(subtitle) = &self.subtitle {
+            packets.push(OutgoingPlayPacket::SetSubtitleText {
+                text: Box::new(apply(subtitle)),
+            });
+        }
+        // The client only shows anything once the title is set, so an empty one is sent for subtitles on their own
+        let text = match &self.title {
+            Some(text) => apply(text),
+            None => ChatComponent::from_text("", ComponentStyle::v1_16()),
+        };
+        packets.push(OutgoingPlayPacket::SetTitleText { text: Box::new(text) });
+        packets
+    }
+}
+
+fn default_fade_in() -> i32 {
+    10
+}
+
+fn default_stay() -> i32 {
+    70
+}
+
+fn default_fade_out() -> i32 {
+    20
+}
@@ -0,0 +1,106 @@
+//! Decodes and pretty-prints packet captures written by fallblock's packet recorder.
+//!
+//! Usage: `fallblock-inspect <capture file>`
+
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{BufRead, BufReader, Cursor},
+};
+
+use fallblock::{
+    capture::{CaptureRecord, CaptureState, Direction},
+    protocol::{
+        handshake::HandshakePacket,
+        login::{IncomingLoginPacket, OutgoingLoginPacket},
+        play::{IncomingPlayPacket, OutgoingPlayPacket},
+        status::{IncomingStatusPacket, OutgoingStatusPacket},
+        Packet,
+    },
+    util::Result,
+};
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: fallblock-inspect <capture file>");
+            std::process::exit(1);
+        }
+    };
+
+    let file = File::open(&path).expect("failed to open capture file");
+    let mut start = None;
+
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.expect("failed to read capture file");
+        if line.is_empty() {
+            continue;
+        }
+        let record: CaptureRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("line {}: invalid capture record: {}", line_number + 1, e);
+                continue;
+            }
+        };
+
+        let start = *start.get_or_insert(record.timestamp);
+        let arrow = match record.direction {
+            Direction::Serverbound => "C->S",
+            Direction::Clientbound => "S->C",
+        };
+        println!(
+            "[+{}ms] {} {:?} {:#04x} ({} bytes)",
+            record.timestamp.saturating_sub(start),
+            arrow,
+            record.state,
+            record.packet_id,
+            record.data.len()
+        );
+
+        match decode(&record) {
+            Ok(Some(decoded)) => println!("{}", indent(&decoded)),
+            Ok(None) => println!("    <unknown packet> {}", hex::encode(&record.data)),
+            Err(e) => println!("    <failed to decode: {}> {}", e, hex::encode(&record.data)),
+        }
+    }
+}
+
+fn decode(record: &CaptureRecord) -> Result<Option<String>> {
+    let rdr = &mut Cursor::new(&record.data[..]);
+    let id = record.packet_id;
+    match (record.state, record.direction) {
+        (CaptureState::Handshake, Direction::Serverbound) => pretty(HandshakePacket::read(id, rdr)?),
+        (CaptureState::Handshake, Direction::Clientbound) => Ok(None),
+        (CaptureState::Status, Direction::Serverbound) => pretty(IncomingStatusPacket::read(id, rdr)?),
+        (CaptureState::Status, Direction::Clientbound) => pretty(OutgoingStatusPacket::read(id, rdr)?),
+        (CaptureState::Login, Direction::Serverbound) => pretty(IncomingLoginPacket::read(id, rdr)?),
+        (CaptureState::Login, Direction::Clientbound) => pretty(OutgoingLoginPacket::read(id, rdr)?),
+        (CaptureState::Play, Direction::Serverbound) => pretty(IncomingPlayPacket::read(id, rdr)?),
+        (CaptureState::Play, Direction::Clientbound) => match OutgoingPlayPacket::read(id, rdr)? {
+            // Printing every block state in a chunk isn't useful to anyone
+            Some(OutgoingPlayPacket::ChunkData { chunk }) => Ok(Some(format!(
+                "ChunkData {{ x: {}, z: {}, sections: {}, non-air blocks: {} }}",
+                chunk.x,
+                chunk.z,
+                chunk.sections.len(),
+                chunk
+                    .sections
+                    .iter()
+                    .flat_map(|s| &s.block_states)
+                    .filter(|s| s.name != "minecraft:air")
+                    .count()
+            ))),
+            packet => pretty(packet),
+        },
+    }
+}
+
+fn pretty<T: Debug>(packet: Option<T>) -> Result<Option<String>> {
+    Ok(packet.map(|p| format!("{:#?}", p)))
+}
+
+fn indent(s: &str) -> String {
+    s.lines().map(|l| format!("    {}", l)).collect::<Vec<_>>().join("\n")
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::{self, File},
+    io::{Cursor, LineWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    protocol::{handshake::HandshakePacket, Packet, ProtocolState},
+    util::Result,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureState {
+    Handshake,
+    Status,
+    Login,
+    Play,
+}
+
+/// A single framed packet, as stored in a capture file (one JSON object per line)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    pub state: CaptureState,
+    pub packet_id: i32,
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// Packet body, excluding the length and packet id
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+/// Records every packet sent or recieved on a connection to a capture file.
+///
+/// Cloning a recorder shares the underlying file, so the same recorder can be
+/// handed to both halves of a connection.
+#[derive(Clone, Debug)]
+pub struct PacketRecorder(Arc<RecorderData>);
+
+#[derive(Debug)]
+struct RecorderData {
+    file: Mutex<LineWriter<File>>,
+    state: Mutex<CaptureState>,
+}
+
+impl PacketRecorder {
+    pub fn create(directory: &Path, peer_addr: SocketAddr) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        // ':' isn't allowed in file names on windows
+        let peer = peer_addr.to_string().replace(':', "_");
+        let path = directory.join(format!("{}-{}.jsonl", now_millis(), peer));
+        info!("capturing packets from {} to {}", peer_addr, path.display());
+        let file = File::create(path)?;
+        Ok(Self(Arc::new(RecorderData {
+            file: Mutex::new(LineWriter::new(file)),
+            state: Mutex::new(CaptureState::Handshake),
+        })))
+    }
+
+    pub fn record(&self, direction: Direction, packet_id: i32, data: &[u8]) {
+        let mut state = self.0.state.lock().expect("capture state lock poisoned");
+        let record = CaptureRecord {
+            direction,
+            state: *state,
+            packet_id,
+            timestamp: now_millis(),
+            data: data.to_vec(),
+        };
+
+        if let Err(e) = self.write_record(&record) {
+            warn!("failed to write packet capture: {}", e);
+        }
+
+        // We don't get told about state changes, so follow along the same way the client does
+        match (*state, direction, packet_id) {
+            (CaptureState::Handshake, Direction::Serverbound, 0x00) => {
+                if let Ok(Some(handshake)) = HandshakePacket::read(packet_id, &mut Cursor::new(data)) {
+                    *state = match handshake.next_state {
+                        ProtocolState::Status => CaptureState::Status,
+                        ProtocolState::Login => CaptureState::Login,
+                    };
+                }
+            }
+            (CaptureState::Login, Direction::Clientbound, 0x02) => *state = CaptureState::Play,
+            _ => {}
+        }
+    }
+
+    fn write_record(&self, record: &CaptureRecord) -> Result<()> {
+        let mut file = self.0.file.lock().expect("capture file lock poisoned");
+        serde_json::to_writer(&mut *file, record)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time is before the unix epoch!?")
+        .as_millis() as u64
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use mc_chat::{ChatComponent, ComponentStyle};
+use serde::Deserialize;
+
+use crate::{placeholders::Placeholders, profile::Profile, store::ServerStore};
+
+#[derive(Debug, Deserialize)]
+pub struct ChatConfig {
+    /// When disabled, players are shown `disabled_message` instead
+    #[serde(default)]
+    pub enabled: bool,
+    /// Used for every chat message, where `{username}` and `{message}` are filled in
+    #[serde(default = "default_format")]
+    pub format: ChatComponent,
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Words to censor, matched case-insensitively (including non-ASCII letters, so "café" also censors "CAFÉ")
+    #[serde(default)]
+    pub filtered_words: Vec<String>,
+    #[serde(default)]
+    pub disabled_message: Option<ChatComponent>,
+    #[serde(default)]
+    pub too_long_message: Option<ChatComponent>,
+    #[serde(default)]
+    pub rate_limited_message: Option<ChatComponent>,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: default_format(),
+            max_length: default_max_length(),
+            rate_limit: RateLimitConfig::default(),
+            filtered_words: Vec::new(),
+            disabled_message: None,
+            too_long_message: None,
+            rate_limited_message: None,
+        }
+    }
+}
+
+fn default_format() -> ChatComponent {
+    ChatComponent::from_text("<{username}> {message}", ComponentStyle::v1_16())
+}
+
+/// Same as the vanilla client
+fn default_max_length() -> usize {
+    256
+}
+
+/// Each player can send `messages` messages every `seconds` seconds
+#[derive(Debug, Deserialize)]
+pub struct RateLimitConfig {
+    pub messages: usize,
+    pub seconds: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages: 5,
+            seconds: 10,
+        }
+    }
+}
+
+/// A hook for checking chat messages before they're sent to anyone
+pub trait ChatFilter: Debug + Send + Sync {
+    /// Returns the message to send, which may have been changed, or `None` to drop it
+    fn filter(&self, username: &str, message: String) -> Option<String>;
+}
+
+/// Replaces configured words with asterisks
+#[derive(Debug)]
+pub struct WordFilter {
+    words: Vec<String>,
+}
+
+impl WordFilter {
+    pub fn new(words: &[String]) -> Self {
+        Self {
+            // Censoring asterisks would do nothing, since that's what they're replaced with
+            words: words
+                .iter()
+                .filter(|w| !w.chars().all(|c| c == '*'))
+                .map(|w| w.chars().flat_map(char::to_lowercase).collect())
+                .collect(),
+        }
+    }
+}
+
+/// Lowercases a message, along with where each byte of the result came from in the original.
+/// Offsets are only set where a lowercased character starts, since a character can lowercase to several
+/// (and change length doing so), and there's one extra for the end of the message.
+fn lowercase_with_offsets(message: &str) -> (String, Vec<Option<usize>>) {
+    let mut lowercase = String::with_capacity(message.len());
+    let mut offsets = Vec::with_capacity(message.len() + 1);
+    for (offset, c) in message.char_indices() {
+        offsets.push(Some(offset));
+        lowercase.extend(c.to_lowercase());
+        offsets.resize(lowercase.len(), None);
+    }
+    offsets.push(Some(message.len()));
+    (lowercase, offsets)
+}
+
+impl ChatFilter for WordFilter {
+    fn filter(&self, _username: &str, mut message: String) -> Option<String> {
+        for word in &self.words {
+            let (lowercase, offsets) = lowercase_with_offsets(&message);
+            let mut filtered = String::with_capacity(message.len());
+            let mut last = 0;
+            for (start, _) in lowercase.match_indices(word.as_str()) {
+                // skip matches that start or end partway through what one character lowercased to
+                let (start, end) = match (offsets[start], offsets[start + word.len()]) {
+                    (Some(start), Some(end)) => (start, end),
+                    _ => continue,
+                };
+                filtered.push_str(&message[last..start]);
+                filtered.push_str(&"*".repeat(message[start..end].chars().count()));
+                last = end;
+            }
+            filtered.push_str(&message[last..]);
+            message = filtered;
+        }
+        Some(message)
+    }
+}
+
+/// The filters set up by the config, which the store rebuilds whenever it's reloaded.
+/// Others can be passed to `ServerStore::new`.
+pub fn default_filters(config: &ChatConfig) -> Vec<Box<dyn ChatFilter>> {
+    let mut filters: Vec<Box<dyn ChatFilter>> = Vec::new();
+    if !config.filtered_words.is_empty() {
+        filters.push(Box::new(WordFilter::new(&config.filtered_words)));
+    }
+    filters
+}
+
+/// What to do with a chat message from a player
+#[derive(Debug)]
+pub enum ChatOutcome {
+    /// Send to everyone in the same world
+    Broadcast(ChatComponent),
+    /// Only tell the sender, ie. why their message wasn't sent
+    Reply(ChatComponent),
+    Ignore,
+}
+
+/// Per-player chat state
+#[derive(Debug, Default)]
+pub struct ChatSession {
+    recent_messages: VecDeque<Instant>,
+}
+
+impl ChatSession {
+    pub fn handle(&mut self, store: &ServerStore, profile: &Profile, username: &str, message: &str) -> ChatOutcome {
+        let config = &store.get_config().chat;
+        if !config.enabled {
+            return ChatOutcome::Reply(reply(&config.disabled_message, "Chat is disabled"));
+        }
+
+        // The vanilla server doesn't allow formatting codes or control characters either
+        let message: String = message.chars().filter(|c| *c != '§' && !c.is_control()).collect();
+        let message = message.trim();
+        if message.is_empty() {
+            return ChatOutcome::Ignore;
+        }
+        if message.chars().count() > config.max_length {
+            return ChatOutcome::Reply(reply(&config.too_long_message, "Your message is too long"));
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(config.rate_limit.seconds);
+        while matches!(self.recent_messages.front(), Some(sent) if now.duration_since(*sent) >= window) {
+            self.recent_messages.pop_front();
+        }
+        if self.recent_messages.len() >= config.rate_limit.messages {
+            return ChatOutcome::Reply(reply(&config.rate_limited_message, "You're sending messages too quickly"));
+        }
+        self.recent_messages.push_back(now);
+
+        let message = match store.filter_chat(username, message.to_string()) {
+            Some(message) => message,
+            None => return ChatOutcome::Ignore,
+        };
+
+        // The message goes last so that anything that looks like a placeholder in it is left alone
+        let placeholders = Placeholders::new(store, profile)
+            .with("username", username)
+            .with("message", message);
+        ChatOutcome::Broadcast(placeholders.apply(&config.format))
+    }
+}
+
+fn reply(message: &Option<ChatComponent>, default: &str) -> ChatComponent {
+    message.clone().unwrap_or_else(|| ChatComponent::from_text(default, ComponentStyle::v1_16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(words: &[&str], message: &str) -> String {
+        let words: Vec<_> = words.iter().map(|w| w.to_string()).collect();
+        WordFilter::new(&words).filter("Player", message.to_string()).unwrap()
+    }
+
+    #[test]
+    fn censors_words_case_insensitively() {
+        assert_eq!(filter(&["bad"], "this is BAD, really bad"), "this is ***, really ***");
+        assert_eq!(filter(&["bad", "worse"], "bad and worse"), "*** and *****");
+        assert_eq!(filter(&["bad"], "all good"), "all good");
+    }
+
+    #[test]
+    fn ignores_words_made_of_asterisks() {
+        assert_eq!(filter(&["*", "**", ""], "a * b ** c"), "a * b ** c");
+    }
+
+    #[test]
+    fn handles_multibyte_words() {
+        assert_eq!(filter(&["café"], "le café est là"), "le **** est là");
+        assert_eq!(filter(&["bad"], "ünïcödé bad ünïcödé"), "ünïcödé *** ünïcödé");
+        assert_eq!(filter(&["日本"], "日本語"), "**語");
+    }
+
+    #[test]
+    fn censors_non_ascii_words_case_insensitively() {
+        assert_eq!(filter(&["café"], "CAFÉ or Café"), "**** or ****");
+        assert_eq!(filter(&["ÉCOLE"], "une école"), "une *****");
+        assert_eq!(filter(&["σοφία"], "ΣΟΦΊΑ"), "*****");
+        // İ lowercases to two characters, so a match on just the first of them isn't a whole character
+        assert_eq!(filter(&["i"], "İ and i"), "İ and *");
+        assert_eq!(filter(&["İx"], "İX"), "**");
+    }
+}
@@ -0,0 +1,302 @@
+use mc_chat::{ChatComponent, ComponentStyle};
+use serde::Deserialize;
+
+use uuid::Uuid;
+
+use crate::{
+    protocol::play::{command_graph::{CommandNode, CommandNodeKind}, ChatPosition, OutgoingPlayPacket},
+    store::{ServerStore, SessionMessage},
+    upstream::UpstreamStatus,
+    util::format_duration,
+};
+
+/// Which of the built-in commands players can use
+#[derive(Debug, Deserialize)]
+pub struct CommandsConfig {
+    #[serde(default = "enabled")]
+    pub retry: bool,
+    #[serde(default = "enabled")]
+    pub status: bool,
+    #[serde(default = "enabled")]
+    pub spawn: bool,
+    #[serde(default = "enabled")]
+    pub help: bool,
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        Self {
+            retry: true,
+            status: true,
+            spawn: true,
+            help: true,
+        }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    Retry,
+    Status,
+    Spawn,
+    Help,
+}
+
+const ALL_COMMANDS: [Command; 4] = [Command::Retry, Command::Status, Command::Spawn, Command::Help];
+
+impl Command {
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Retry => "retry",
+            Command::Status => "status",
+            Command::Spawn => "spawn",
+            Command::Help => "help",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Command::Retry => "Try to join the main server again",
+            Command::Status => "Check whether the main server is up",
+            Command::Spawn => "Go back to spawn",
+            Command::Help => "List the available commands",
+        }
+    }
+
+    fn is_enabled(self, config: &CommandsConfig) -> bool {
+        match self {
+            Command::Retry => config.retry,
+            Command::Status => config.status,
+            Command::Spawn => config.spawn,
+            Command::Help => config.help,
+        }
+    }
+}
+
+/// What the connection needs to do to carry out a command
+#[derive(Debug)]
+pub enum CommandAction {
+    Reply(Box<ChatComponent>),
+    /// Teleport the player back to the spawn point
+    Respawn,
+    /// Send the player to the upstream server
+    Transfer,
+}
+
+pub fn enabled_commands(store: &ServerStore) -> Vec<Command> {
+    let config = &store.get_config().commands;
+    ALL_COMMANDS.into_iter().filter(|c| c.is_enabled(config)).collect()
+}
+
+/// Builds the graph for Declare Commands, returning the nodes and the index of the root
+pub fn command_graph(commands: &[Command]) -> (Vec<CommandNode>, i32) {
+    let mut nodes = vec![CommandNode {
+        kind: CommandNodeKind::Root,
+        executable: false,
+        children: (1..=commands.len() as i32).collect(),
+    }];
+    nodes.extend(commands.iter().map(|command| CommandNode {
+        kind: CommandNodeKind::Literal(command.name().to_string()),
+        executable: true,
+        children: Vec::new(),
+    }));
+    (nodes, 0)
+}
+
+/// Runs a command, given without the leading `/`
+pub fn execute(store: &ServerStore, input: &str) -> CommandAction {
+    let name = input.split_whitespace().next().unwrap_or_default();
+    let command = enabled_commands(store)
+        .into_iter()
+        .find(|c| c.name().eq_ignore_ascii_case(name));
+
+    match command {
+        Some(Command::Retry) => match store.get_upstream_status() {
+            _ if store.get_config().upstream.is_none() => CommandAction::Reply(text("No main server is configured")),
+            UpstreamStatus::Unknown => CommandAction::Reply(text("The server's status isn't known yet, try again in a moment")),
+            UpstreamStatus::Down { .. } => CommandAction::Reply(text("The server is still down, try again in a bit")),
+            // Skipping the queue would defeat the point of it
+            _ if store.get_config().queue.is_some() => {
+                CommandAction::Reply(text("You'll be sent back automatically when it's your turn"))
+            }
+            _ => CommandAction::Transfer,
+        },
+        Some(Command::Status) => CommandAction::Reply(text(&status_message(store))),
+        Some(Command::Spawn) => CommandAction::Respawn,
+        Some(Command::Help) => {
+            let lines: Vec<_> = enabled_commands(store)
+                .iter()
+                .map(|c| format!("/{} - {}", c.name(), c.description()))
+                .collect();
+            CommandAction::Reply(text(&lines.join("\n")))
+        }
+        None => CommandAction::Reply(text("Unknown command. Type \"/help\" for help.")),
+    }
+}
+
+/// Commands for operators, ie. over RCON, which players can't use since there's no permission system
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConsoleCommand {
+    List,
+    Kick,
+    Say,
+    Reload,
+    Queue,
+    TransferAll,
+    Status,
+    Help,
+}
+
+const ALL_CONSOLE_COMMANDS: [ConsoleCommand; 8] = [
+    ConsoleCommand::List,
+    ConsoleCommand::Kick,
+    ConsoleCommand::Say,
+    ConsoleCommand::Reload,
+    ConsoleCommand::Queue,
+    ConsoleCommand::TransferAll,
+    ConsoleCommand::Status,
+    ConsoleCommand::Help,
+];
+
+impl ConsoleCommand {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConsoleCommand::List => "list",
+            ConsoleCommand::Kick => "kick",
+            ConsoleCommand::Say => "say",
+            ConsoleCommand::Reload => "reload",
+            ConsoleCommand::Queue => "queue",
+            ConsoleCommand::TransferAll => "transfer-all",
+            ConsoleCommand::Status => "status",
+            ConsoleCommand::Help => "help",
+        }
+    }
+
+    pub fn usage(self) -> &'static str {
+        match self {
+            ConsoleCommand::Kick => "kick <player> [reason]",
+            ConsoleCommand::Say => "say <message>",
+            command => command.name(),
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ConsoleCommand::List => "List the players online",
+            ConsoleCommand::Kick => "Disconnect a player, by username or UUID",
+            ConsoleCommand::Say => "Send a message to everyone",
+            ConsoleCommand::Reload => "Reload the config and maps",
+            ConsoleCommand::Queue => "Show how many players are queued",
+            ConsoleCommand::TransferAll => "Send everyone to the main server now, skipping the queue",
+            ConsoleCommand::Status => Command::Status.description(),
+            ConsoleCommand::Help => Command::Help.description(),
+        }
+    }
+}
+
+/// Runs an operator command, returning the output as plain text.
+/// Output for commands that also exist in vanilla matches it, so that existing scripts keep working.
+pub async fn execute_console(store: &ServerStore, input: &str) -> String {
+    let input = input.trim().trim_start_matches('/');
+    let (name, args) = input.split_once(' ').unwrap_or((input, ""));
+    let args = args.trim();
+    let command = ALL_CONSOLE_COMMANDS.into_iter().find(|c| c.name().eq_ignore_ascii_case(name));
+
+    match command {
+        Some(ConsoleCommand::List) => {
+            let players = store.get_online_players();
+            let max = store.max_players(&store.get_profile(""));
+            let names: Vec<_> = players.iter().map(|p| p.username.as_str()).collect();
+            format!("There are {} of a max of {} players online: {}", players.len(), max, names.join(", "))
+        }
+        Some(ConsoleCommand::Kick) => {
+            let (player, reason) = args.split_once(' ').unwrap_or((args, ""));
+            if player.is_empty() {
+                return format!("Usage: {}", ConsoleCommand::Kick.usage());
+            }
+            let session = match store.find_session(player) {
+                Some(session) => session,
+                None => return "No player was found".to_string(),
+            };
+            let reason = match reason.trim() {
+                "" => "Kicked by an operator",
+                reason => reason,
+            };
+            info!(uuid = %session.player.uuid, username = %session.player.username, "kicking player from the console");
+            session.send(SessionMessage::Disconnect(text(reason)));
+            format!("Kicked {}: {}", session.player.username, reason)
+        }
+        Some(ConsoleCommand::Say) => {
+            if args.is_empty() {
+                return format!("Usage: {}", ConsoleCommand::Say.usage());
+            }
+            let packet = OutgoingPlayPacket::ChatMessage {
+                message: *text(&format!("[Server] {}", args)),
+                position: ChatPosition::System,
+                sender: Uuid::nil(),
+            };
+            store.broadcast(SessionMessage::Packet(Box::new(packet)));
+            String::new()
+        }
+        Some(ConsoleCommand::Reload) => {
+            let reload_store = store.clone();
+            match tokio::task::spawn_blocking(move || reload_store.reload_config()).await {
+                Ok(Ok(restart_required)) if restart_required.is_empty() => {
+                    info!("reloaded config from the console");
+                    "Reloaded the config".to_string()
+                }
+                Ok(Ok(restart_required)) => {
+                    info!("reloaded config from the console");
+                    format!(
+                        "Reloaded the config, but changes to {} only take effect after a restart",
+                        restart_required.join(", ")
+                    )
+                }
+                Ok(Err(e)) => {
+                    warn!("failed to reload config: {}", e);
+                    format!("Failed to reload the config: {}", e)
+                }
+                Err(e) => {
+                    error!("config reload panicked: {}", e);
+                    "Failed to reload the config".to_string()
+                }
+            }
+        }
+        Some(ConsoleCommand::Queue) => format!(
+            "There are {} players in the queue, and the main server is {}",
+            store.queue_len(),
+            store.get_upstream_status().name()
+        ),
+        Some(ConsoleCommand::TransferAll) => {
+            let transferred = store.transfer_all();
+            info!("transferring {} players to the upstream from the console", transferred);
+            format!("Sent {} players to the main server", transferred)
+        }
+        Some(ConsoleCommand::Status) => status_message(store),
+        Some(ConsoleCommand::Help) => ALL_CONSOLE_COMMANDS
+            .iter()
+            .map(|c| format!("{} - {}", c.usage(), c.description()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => format!("Unknown command: {}", name),
+    }
+}
+
+fn status_message(store: &ServerStore) -> String {
+    match store.get_upstream_status() {
+        UpstreamStatus::Unknown => "The server's status isn't known yet".to_string(),
+        UpstreamStatus::Up => "The server is online".to_string(),
+        status @ UpstreamStatus::Down { .. } => format!(
+            "The server has been offline for {}",
+            format_duration(status.downtime().unwrap_or_default())
+        ),
+    }
+}
+
+fn text(s: &str) -> Box<ChatComponent> {
+    Box::new(ChatComponent::from_text(s, ComponentStyle::v1_16()))
+}
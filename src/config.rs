@@ -1,21 +1,270 @@
-use std::{fs::File, path::PathBuf};
+use std::{collections::BTreeMap, fs::File, net::SocketAddr, path::PathBuf};
 
+use mc_chat::{ChatComponent, ComponentStyle};
 use serde::Deserialize;
 
-use crate::protocol::{play::JoinGameData, status::ServerListPingResponse};
+use crate::{admin::AdminConfig, announcements::AnnouncementConfig, chat::ChatConfig, commands::CommandsConfig, profile::VirtualHostConfig, queue::{QueueConfig, QueueDisplayConfig}, rcon::RconConfig, protocol::{forwarding::ForwardingMode, play::JoinGameData, status::ServerListPingResponse}, upstream::UpstreamConfig, util::{ProtocolError, Result}};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub server_brand: String,
     pub join_game_data: JoinGameData,
     pub spawn_point: (f64, f64, f64),
+    #[serde(default)]
+    pub bounds: BoundsConfig,
     pub map_file: PathBuf,
     pub status: ServerListPingResponse,
+    /// 64x64 PNG to use as the favicon, instead of the one in `status`
+    #[serde(default)]
+    pub favicon_file: Option<PathBuf>,
+    /// Deprecated in favour of `favicon_file` in `virtual_hosts`, which these are turned into when the config is read
+    #[serde(default)]
+    pub favicon_files: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    pub players: PlayersConfig,
+    /// MOTDs to show in the server list instead of the status description
+    #[serde(default)]
+    pub motd: Option<MotdConfig>,
+    /// The main server, which is health checked so that players can be told whether it's up
+    #[serde(default)]
+    pub upstream: Option<UpstreamConfig>,
+    /// Lets players in the same world see each other moving around
+    #[serde(default)]
+    pub shared_world: Option<SharedWorldConfig>,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    /// Throttles how quickly players are sent back to the upstream once it's up again
+    #[serde(default)]
+    pub queue: Option<QueueConfig>,
+    #[serde(default)]
+    pub tab_list: Option<TabListConfig>,
+    /// Chat messages and titles sent to players after they join, such as where to find status updates
+    #[serde(default)]
+    pub announcements: Vec<AnnouncementConfig>,
+    /// Boss bar and action bar showing players their place in the queue
+    #[serde(default)]
+    pub queue_display: Option<QueueDisplayConfig>,
+    /// Stops players from seeing each other in the shared world
+    #[serde(default)]
+    pub hide_players: bool,
+    /// Per-hostname overrides, checked in order against the address players connected with
+    #[serde(default)]
+    pub virtual_hosts: Vec<VirtualHostConfig>,
+    #[serde(default)]
+    pub forwarding_mode: ForwardingMode,
     #[serde(default)]
     pub modern_forwarding_key: Option<String>,
+    /// Tokens that BungeeGuard is allowed to send, when `forwarding_mode` is `bungeeguard`
+    #[serde(default)]
+    pub bungeeguard_tokens: Vec<String>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Shown to players who try to join while maintenance mode is on
+    #[serde(default)]
+    pub maintenance_message: Option<ChatComponent>,
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub rcon: Option<RconConfig>,
+    /// Address to serve Prometheus metrics on, at `/metrics`
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+    /// JSON file to remember players' last positions in, so that they come back to where they left off
+    #[serde(default)]
+    pub player_data_file: Option<PathBuf>,
+    /// Directory to write per-connection packet captures to, if capturing is enabled
+    #[serde(default)]
+    pub capture_directory: Option<PathBuf>,
+}
+
+impl Config {
+    /// Names the settings that differ in `new` but are only read at startup, so reloading can't apply them
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.upstream.as_ref().map(|u| (&u.address, u.check_interval))
+            != new.upstream.as_ref().map(|u| (&u.address, u.check_interval))
+        {
+            changed.push("upstream");
+        }
+        if self.queue.as_ref().map(|q| (q.release_batch, q.release_interval))
+            != new.queue.as_ref().map(|q| (q.release_batch, q.release_interval))
+        {
+            changed.push("queue");
+        }
+        if self.player_data_file != new.player_data_file {
+            changed.push("player_data_file");
+        }
+        if self.metrics_address != new.metrics_address {
+            changed.push("metrics_address");
+        }
+        if self.admin.as_ref().map(|a| (a.address, &a.token)) != new.admin.as_ref().map(|a| (a.address, &a.token)) {
+            changed.push("admin");
+        }
+        if self.rcon.as_ref().map(|r| (r.address, &r.password)) != new.rcon.as_ref().map(|r| (r.address, &r.password)) {
+            changed.push("rcon");
+        }
+        changed
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PlayersConfig {
+    /// Maximum number of players allowed in at once. Also used as the max player count in the server list
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    /// Message shown to players who can't join because `max_connections` has been reached
+    #[serde(default)]
+    pub full_message: Option<ChatComponent>,
+    /// Lines shown when hovering over the player count, instead of the names of connected players
+    #[serde(default)]
+    pub hover_text: Option<Vec<String>>,
+    /// Name shown in the tab list instead of the player's username, which can use `{username}`
+    #[serde(default)]
+    pub display_name: Option<ChatComponent>,
+}
+
+/// What happens to connected players when the server is stopped
+#[derive(Debug, Deserialize)]
+pub struct ShutdownConfig {
+    #[serde(default = "default_shutdown_message")]
+    pub message: ChatComponent,
+    /// Another server behind the proxy to send players to instead of disconnecting them
+    #[serde(default)]
+    pub transfer_to: Option<String>,
+    /// Seconds to wait for players to leave before exiting anyway
+    #[serde(default = "default_shutdown_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            message: default_shutdown_message(),
+            transfer_to: None,
+            timeout: default_shutdown_timeout(),
+        }
+    }
+}
+
+fn default_shutdown_message() -> ChatComponent {
+    ChatComponent::from_text("The server is restarting, please reconnect in a moment", ComponentStyle::v1_16())
+}
+
+fn default_shutdown_timeout() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharedWorldConfig {
+    /// The most other players anyone will see at once, to keep things from getting too crowded
+    #[serde(default = "default_max_visible_players")]
+    pub max_visible_players: usize,
+}
+
+fn default_max_visible_players() -> usize {
+    50
+}
+
+/// Text shown above and below the player list, which can use placeholders
+#[derive(Clone, Debug, Deserialize)]
+pub struct TabListConfig {
+    #[serde(default)]
+    pub header: Option<ChatComponent>,
+    #[serde(default)]
+    pub footer: Option<ChatComponent>,
+    /// Seconds between updates
+    #[serde(default = "default_tab_list_refresh_interval")]
+    pub refresh_interval: u64,
+}
+
+fn default_tab_list_refresh_interval() -> u64 {
+    5
+}
+
+/// Where players can go before they're sent back to the spawn point
+#[derive(Clone, Debug, Deserialize)]
+pub struct BoundsConfig {
+    /// Players who fall below this are respawned. Defaults to `void_margin` blocks below the bottom of the dimension.
+    #[serde(default)]
+    pub min_y: Option<f64>,
+    #[serde(default = "default_void_margin")]
+    pub void_margin: f64,
+    /// A box players have to stay inside, ie. the edges of the map
+    #[serde(default)]
+    pub area: Option<AreaConfig>,
+}
+
+/// Opposite corners of a box, in either order
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct AreaConfig {
+    pub from: (f64, f64, f64),
+    pub to: (f64, f64, f64),
+}
+
+impl Default for BoundsConfig {
+    fn default() -> Self {
+        Self {
+            min_y: None,
+            void_margin: default_void_margin(),
+            area: None,
+        }
+    }
+}
+
+fn default_void_margin() -> f64 {
+    16.0
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MotdConfig {
+    pub messages: Vec<ChatComponent>,
+    #[serde(default)]
+    pub rotation: MotdRotation,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotdRotation {
+    /// Show the next message every time the server is pinged
+    #[default]
+    PerPing,
+    /// Show the next message every given number of seconds
+    Interval(u64),
 }
 
 pub fn load_config() -> Config {
-    let mut file = File::open("config.json").expect("failed to open config file");
-    serde_json::from_reader(&mut file).expect("failed to parse config")
+    read_config().expect("failed to load config")
+}
+
+/// Reads and checks `config.json`
+pub fn read_config() -> Result<Config> {
+    let mut file = File::open("config.json")?;
+    let mut config: Config = serde_json::from_reader(&mut file)?;
+
+    if !config.favicon_files.is_empty() {
+        warn!("favicon_files is deprecated, use favicon_file in virtual_hosts instead");
+        // Added after the configured virtual hosts, so that they still take priority
+        let favicon_files = std::mem::take(&mut config.favicon_files);
+        config.virtual_hosts.extend(favicon_files.into_iter().map(|(host, path)| VirtualHostConfig {
+            hosts: vec![host],
+            favicon_file: Some(path),
+            ..VirtualHostConfig::default()
+        }));
+    }
+
+    // Before forwarding_mode existed, setting the key was how modern forwarding was turned on
+    if config.forwarding_mode == ForwardingMode::None && config.modern_forwarding_key.is_some() {
+        config.forwarding_mode = ForwardingMode::Velocity;
+    }
+    match config.forwarding_mode {
+        ForwardingMode::Velocity if config.modern_forwarding_key.is_none() => Err(ProtocolError::InvalidConfig(
+            "modern_forwarding_key must be set to use velocity forwarding".to_string(),
+        )),
+        ForwardingMode::BungeeGuard if config.bungeeguard_tokens.is_empty() => Err(ProtocolError::InvalidConfig(
+            "bungeeguard_tokens must be set to use bungeeguard forwarding".to_string(),
+        )),
+        _ => Ok(config),
+    }
 }
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{io::PacketWriter, util::Result};
+use crate::{io::{PacketField, PacketReader, PacketWriter}, util::{ProtocolError, Result}};
 
 pub const PROTOCOL_VERSION: i32 = 758;
 
@@ -10,7 +10,7 @@ pub struct ProtocolVersion {
     name: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
 pub enum Gamemode {
     Survival,
     Creative,
@@ -19,12 +19,32 @@ pub enum Gamemode {
 }
 
 impl Gamemode {
-    pub fn write<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
-        wr.write_ubyte(match self {
+    pub fn id(self) -> u8 {
+        match self {
             Gamemode::Survival => 0,
             Gamemode::Creative => 1,
             Gamemode::Adventure => 2,
             Gamemode::Spectator => 3,
-        })
+        }
+    }
+
+    pub fn from_id(id: i32) -> Result<Self> {
+        match id {
+            0 => Ok(Gamemode::Survival),
+            1 => Ok(Gamemode::Creative),
+            2 => Ok(Gamemode::Adventure),
+            3 => Ok(Gamemode::Spectator),
+            v => Err(ProtocolError::InvalidEnumValue(v)),
+        }
+    }
+}
+
+impl PacketField for Gamemode {
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_ubyte(self.id())
+    }
+
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        Gamemode::from_id(rdr.read_ubyte()? as i32)
     }
 }
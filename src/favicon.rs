@@ -0,0 +1,29 @@
+use std::{fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::util::{ProtocolError, Result};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Loads a PNG file, checks that it's usable as a server icon and encodes it as a data URI
+pub fn load_favicon(path: &Path) -> Result<String> {
+    let data = fs::read(path)?;
+
+    // The IHDR chunk always comes first, so the dimensions are always at the same place
+    if data.len() < 24 || &data[..8] != PNG_SIGNATURE || &data[12..16] != b"IHDR" {
+        return Err(ProtocolError::InvalidFavicon(format!("{} is not a PNG file", path.display())));
+    }
+    let width = u32::from_be_bytes([data[16], data[17], data[18], data[19]]);
+    let height = u32::from_be_bytes([data[20], data[21], data[22], data[23]]);
+    if (width, height) != (64, 64) {
+        return Err(ProtocolError::InvalidFavicon(format!(
+            "{} is {}x{}, but favicons must be 64x64",
+            path.display(),
+            width,
+            height
+        )));
+    }
+
+    Ok(format!("data:image/png;base64,{}", STANDARD.encode(&data)))
+}
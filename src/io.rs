@@ -1,6 +1,9 @@
 use byteorder::BigEndian;
+use serde::de::DeserializeOwned;
 use uuid::Uuid;
 
+pub use fallblock_derive::PacketField;
+
 use crate::util::{Result, ProtocolError};
 
 // I am eternally greatful to the wonderful folk who maintain wiki.vg
@@ -26,6 +29,8 @@ pub trait PacketReader: std::fmt::Debug {
 
     fn read_string(&mut self, max_len: i32) -> Result<String>;
 
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>>;
+
     fn read_var_int(&mut self) -> Result<i32> {
         let mut value = 0;
         let mut length = 0;
@@ -50,7 +55,7 @@ pub trait PacketReader: std::fmt::Debug {
             let current_byte = self.read_ubyte()?;
             value |= ((current_byte & 0x7F) as i64) << (length * 7);
             length += 1;
-            if length > 5 {
+            if length > 10 {
                 return Err(ProtocolError::VarIntTooLong);
             }
             if (current_byte & 0x80) != 0x80 {
@@ -68,6 +73,41 @@ pub trait PacketReader: std::fmt::Debug {
         let data = (msb as u128) << 64 | (lsb as u128);
         Ok(Uuid::from_u128(data))
     }
+
+    fn read_nbt<T: DeserializeOwned>(&mut self) -> Result<T> where Self: Sized {
+        Ok(nbt::from_reader(ReadAdapter(self))?)
+    }
+
+    fn read_position(&mut self) -> Result<(i32, i32, i32)> {
+        let v = self.read_long()?;
+        let x = v >> 38;
+        let y = v << 52 >> 52;
+        let z = v << 26 >> 38;
+        Ok((x as i32, y as i32, z as i32))
+    }
+
+    fn read_json<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let json = self.read_string(32767)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Lets a [`PacketReader`] be used where a [`std::io::Read`] is expected (ie. by the NBT decoder).
+/// Reads a byte at a time so that nothing past the end of the value gets consumed.
+struct ReadAdapter<'a, R>(&'a mut R);
+
+impl<R: PacketReader> std::io::Read for ReadAdapter<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            match self.0.read_ubyte() {
+                Ok(v) => *b = v,
+                Err(ProtocolError::IOError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(i),
+                Err(ProtocolError::IOError(e)) => return Err(e),
+                Err(e) => return Err(std::io::Error::other(e)),
+            }
+        }
+        Ok(buf.len())
+    }
 }
 
 impl<T: byteorder::ReadBytesExt + std::fmt::Debug> PacketReader for T {
@@ -117,6 +157,12 @@ impl<T: byteorder::ReadBytesExt + std::fmt::Debug> PacketReader for T {
         Ok(String::from_utf8(buffer)?)
     }
 
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; len];
+        self.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
     fn read_remaining(&mut self) -> Result<Vec<u8>> {
         let mut buffer = vec![];
         self.read_to_end(&mut buffer)?;
@@ -158,7 +204,7 @@ pub trait PacketWriter {
     }
 
     fn write_var_long(&mut self, v: i64) -> Result<()> {
-        let mut v = v;
+        let mut v = v as u64;
         loop {
             if (v & !0x7F) == 0 {
                 return self.write_ubyte(v as u8);
@@ -202,8 +248,8 @@ pub trait PacketWriter {
 
     fn write_position(&mut self, x: i32, y: i32, z: i32) -> Result<()> {
         let x = (x as u64) & 0x3FFFFFF;
-        let y = (y as u64) & 0x3FFFFFF;
-        let z = (z as u64) & 0xFFF;
+        let y = (y as u64) & 0xFFF;
+        let z = (z as u64) & 0x3FFFFFF;
         self.write_ulong(x << 38 | z << 12 | y)?;
         Ok(())
     }
@@ -267,3 +313,127 @@ impl<T: byteorder::WriteBytesExt> PacketWriter for T {
         }
     }
 }
+
+/// A value that knows how to read and write itself, so that it can be used as a field in a `#[derive(Packet)]`.
+/// Usually implemented with `#[derive(PacketField)]`.
+pub trait PacketField: Sized {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self>;
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()>;
+}
+
+macro_rules! packet_field {
+    ($ty:ty, $read:ident, $write:ident) => {
+        impl PacketField for $ty {
+            fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+                rdr.$read()
+            }
+
+            fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+                wr.$write(*self)
+            }
+        }
+    };
+}
+
+packet_field!(bool, read_bool, write_bool);
+packet_field!(i8, read_byte, write_byte);
+packet_field!(u8, read_ubyte, write_ubyte);
+packet_field!(i16, read_short, write_short);
+packet_field!(u16, read_ushort, write_ushort);
+packet_field!(i32, read_int, write_int);
+packet_field!(i64, read_long, write_long);
+packet_field!(u64, read_ulong, write_ulong);
+packet_field!(f32, read_float, write_float);
+packet_field!(f64, read_double, write_double);
+
+impl PacketField for String {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        rdr.read_string(32767)
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_string(self, 32767)
+    }
+}
+
+impl PacketField for Uuid {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        rdr.read_uuid()
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_uuid(self)
+    }
+}
+
+/// Arrays are prefixed with their length as a var int
+impl<T: PacketField> PacketField for Vec<T> {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let length = rdr.read_var_int()?;
+        // The length comes from the other end, so it isn't trusted with more than a little up front
+        let mut arr = Vec::with_capacity(length.clamp(0, 4096) as usize);
+        for _ in 0..length {
+            arr.push(T::read_field(rdr)?);
+        }
+        Ok(arr)
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_var_int(self.len() as i32)?;
+        for v in self {
+            v.write_field(wr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Optional values are prefixed with a boolean saying whether they're present
+impl<T: PacketField> PacketField for Option<T> {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        if rdr.read_bool()? {
+            Ok(Some(T::read_field(rdr)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_bool(self.is_some())?;
+        if let Some(v) = self {
+            v.write_field(wr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_position(x: i32, y: i32, z: i32) -> (i32, i32, i32) {
+        let mut buf = Vec::new();
+        buf.write_position(x, y, z).unwrap();
+        (&buf[..]).read_position().unwrap()
+    }
+
+    #[test]
+    fn encodes_positions() {
+        // Example from the protocol documentation
+        let mut buf = Vec::new();
+        buf.write_position(18357644, 831, -20882616).unwrap();
+        assert_eq!(buf, 0b01000110_00000111_01100011_00101100_00010101_10110100_10000011_00111111_u64.to_be_bytes());
+    }
+
+    #[test]
+    fn round_trips_positions() {
+        for position in [
+            (0, 0, 0),
+            (18357644, 831, -20882616),
+            (-1234567, -64, 7654321),
+            (-33554432, -2048, 33554431),
+            (33554431, 2047, -33554432),
+        ] {
+            assert_eq!(round_trip_position(position.0, position.1, position.2), position);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+pub mod protocol;
+pub mod io;
+pub mod util;
+pub mod constants;
+pub mod store;
+pub mod world;
+pub mod config;
+pub mod capture;
+pub mod upstream;
+pub mod placeholders;
+pub mod favicon;
+pub mod profile;
+pub mod chat;
+pub mod commands;
+pub mod queue;
+pub mod announcements;
+pub mod player_data;
+pub mod metrics;
+pub mod admin;
+pub mod rcon;
+
+#[macro_use]
+extern crate tracing;
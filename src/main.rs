@@ -1,35 +1,42 @@
 use std::net::SocketAddr;
-
+use std::sync::Arc;
+use std::time::Duration;
+
+use fallblock::admin;
+use fallblock::capture::PacketRecorder;
+use fallblock::config;
+use fallblock::protocol;
+use fallblock::queue;
+use fallblock::rcon;
+use fallblock::upstream;
+use fallblock::metrics::{self, ConnectionState};
 use futures::Sink;
 use futures::TryStream;
 use futures::TryStreamExt;
-use protocol::MinecraftFramedCodec;
-use protocol::PacketData;
-use protocol::PacketPayload;
-use protocol::ProtocolState;
-use protocol::handshake::HandshakePacket;
+use fallblock::protocol::MinecraftFramedCodec;
+use fallblock::protocol::PacketData;
+use fallblock::protocol::Packet;
+use fallblock::protocol::PacketPayload;
+use fallblock::protocol::ProtocolState;
+use fallblock::protocol::handshake::HandshakePacket;
 use tokio::net::TcpStream;
 
 use tokio::net::TcpListener;
 use tokio_util::codec::FramedRead;
 use tokio_util::codec::FramedWrite;
-use util::ProtocolError;
-use crate::constants::PROTOCOL_VERSION;
-use crate::store::ServerStore;
-use crate::util::Result;
-use crate::world::map_template;
-
-pub mod protocol;
-pub mod io;
-pub mod util;
-pub mod constants;
-pub mod store;
-pub mod world;
-pub mod config;
+use fallblock::util::ProtocolError;
+use fallblock::constants::PROTOCOL_VERSION;
+use fallblock::store::{ConnectionInfo, ServerStore, SessionMessage};
+use fallblock::util::Result;
+use fallblock::profile::Profiles;
+use fallblock::player_data::{self, PlayerData};
 
 #[macro_use]
 extern crate tracing;
 
+/// How long to wait for a session that's behind to make room for the shutdown message
+const SHUTDOWN_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -37,23 +44,93 @@ async fn main() -> Result<()> {
 
     let config = config::load_config();
 
-    info!("Loading chunks...");
-    let map_template = map_template::load_template(&config.map_file)
-        .expect("failed to read map file");
+    let profiles = Profiles::load(&config, None)
+        .expect("failed to load profiles");
     info!("World ready");
 
-    let store = ServerStore::new(config, map_template);
+    let player_data = config.player_data_file.as_deref()
+        .map(PlayerData::load)
+        .transpose()
+        .expect("failed to load player data");
+    let store = ServerStore::new(config, profiles, Vec::new(), player_data);
+    tokio::spawn(upstream::run_health_check(store.clone()));
+    tokio::spawn(queue::run_release(store.clone()));
+    tokio::spawn(player_data::run_save(store.clone()));
+    if let Some(address) = store.get_config().metrics_address {
+        tokio::spawn(metrics::serve(store.clone(), address));
+    }
+    if let Some(config) = store.get_config().admin.clone() {
+        tokio::spawn(admin::serve(store.clone(), config));
+    }
+    if let Some(config) = store.get_config().rcon.clone() {
+        tokio::spawn(rcon::serve(store.clone(), config));
+    }
 
     let listener = TcpListener::bind("127.0.0.1:25566").await?;
     info!("Listening on {}", listener.local_addr()?);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        let (stream, peer_addr) = listener.accept().await?;
-        let store = store.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(peer_addr, stream, store).await {
-                error!("failed to handle connection from {}: {}", peer_addr, e);
+        tokio::select! {
+            result = listener.accept() => {
+                let (stream, peer_addr) = result?;
+                let store = store.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(peer_addr, stream, store).await {
+                        error!("failed to handle connection from {}: {}", peer_addr, e);
+                    }
+                });
             }
-        });
+            _ = &mut shutdown => break,
+        }
+    }
+
+    info!("Shutting down");
+    drop(listener);
+    let missed = store.broadcast_within(SessionMessage::Shutdown, SHUTDOWN_SEND_TIMEOUT).await;
+    if missed > 0 {
+        warn!("couldn't tell {} sessions that the server is stopping", missed);
+    }
+    let timeout = Duration::from_secs(store.get_config().shutdown.timeout);
+    let all_left = async {
+        while store.online_player_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if tokio::time::timeout(timeout, all_left).await.is_err() {
+        warn!("{} players were still connected after {:?}, exiting anyway", store.online_player_count(), timeout);
+    }
+    player_data::save(&store).await;
+    Ok(())
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for ctrl+c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
 }
 
@@ -61,18 +138,39 @@ async fn main() -> Result<()> {
 async fn handle_connection(peer_addr: SocketAddr, stream: TcpStream, store: ServerStore) -> Result<()> {
     info!("handling connection from {}", peer_addr);
 
+    let recorder = match &store.get_config().capture_directory {
+        Some(directory) => match PacketRecorder::create(directory, peer_addr) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                warn!("failed to start packet capture for {}: {}", peer_addr, e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let metrics = Arc::clone(store.get_metrics());
+    let mut connection = metrics.track_connection();
+
     let (rd, wr) = tokio::io::split(stream);
-    let mut framed_read = FramedRead::new(rd, MinecraftFramedCodec);
-    let mut framed_write = FramedWrite::new(wr, MinecraftFramedCodec);
+    let mut framed_read = FramedRead::new(rd, MinecraftFramedCodec::new(recorder.clone()));
+    let mut framed_write = FramedWrite::new(metrics.count_bytes_sent(wr), MinecraftFramedCodec::new(recorder));
 
     let handshake = handshake(&mut framed_read).await?;
 
     if let Some(handshake) = handshake {
         info!("got handshake packet: {:?}", handshake);
+        metrics.record_protocol_version(handshake.protocol_version);
         if handshake.protocol_version != PROTOCOL_VERSION {
             warn!("unsupported protocol version: {}", handshake.protocol_version);
         } else {
-            handle_next_phase(&mut framed_read, &mut framed_write, handshake.next_state, store).await?;
+            let login = matches!(handshake.next_state, ProtocolState::Login);
+            connection.set_state(if login { ConnectionState::Login } else { ConnectionState::Status });
+            let result = handle_next_phase(&mut framed_read, &mut framed_write, peer_addr, handshake, store).await;
+            if login && result.is_err() {
+                metrics.record_disconnect("error");
+            }
+            result?;
             info!("Connection handling complete!");
         }
     }
@@ -83,20 +181,25 @@ async fn handle_connection(peer_addr: SocketAddr, stream: TcpStream, store: Serv
 async fn handle_next_phase<
     R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin,
     W: Sink<PacketPayload, Error = ProtocolError> + Unpin,
->(rdr: &mut R, wr: &mut W, next_state: ProtocolState, store: ServerStore) -> Result<()> {
-    match next_state {
-        ProtocolState::Login => protocol::login::handle(rdr, wr, store).await,
-        ProtocolState::Status => protocol::status::handle(rdr, wr, store).await,
+>(rdr: &mut R, wr: &mut W, peer_addr: SocketAddr, handshake: HandshakePacket, store: ServerStore) -> Result<()> {
+    match handshake.next_state {
+        ProtocolState::Login => {
+            let profile = store.get_profile(&handshake.server_address);
+            let connection = ConnectionInfo {
+                address: peer_addr.ip(),
+                protocol_version: handshake.protocol_version,
+            };
+            protocol::login::handle(rdr, wr, &handshake.server_address, connection, profile, store).await
+        }
+        ProtocolState::Status => protocol::status::handle(rdr, wr, &handshake.server_address, store).await,
     }
 }
 
 async fn handshake<R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin>(rdr: &mut R) -> Result<Option<HandshakePacket>> {
     if let Some(mut packet) = rdr.try_next().await? {
-        if packet.packet_id != 0 {
-            Err(ProtocolError::MissingHandshake)
-        } else {
-            HandshakePacket::read(&mut packet).map(Option::Some)
-        }
+        HandshakePacket::read(packet.packet_id, &mut packet)?
+            .ok_or(ProtocolError::MissingHandshake)
+            .map(Option::Some)
     } else {
         Ok(None)
     }
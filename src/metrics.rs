@@ -0,0 +1,324 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write as _,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tokio::io::AsyncWrite;
+
+use crate::{constants::PROTOCOL_VERSION, protocol::forwarding::ForwardingMode, store::ServerStore};
+
+/// Which part of the protocol a connection is in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    Handshake,
+    Status,
+    Login,
+    Play,
+}
+
+const CONNECTION_STATES: [ConnectionState; 4] = [
+    ConnectionState::Handshake,
+    ConnectionState::Status,
+    ConnectionState::Login,
+    ConnectionState::Play,
+];
+
+impl ConnectionState {
+    fn name(self) -> &'static str {
+        match self {
+            ConnectionState::Handshake => "handshake",
+            ConnectionState::Status => "status",
+            ConnectionState::Login => "login",
+            ConnectionState::Play => "play",
+        }
+    }
+}
+
+/// Release versions from 1.8 to 1.19.2, which are the only ones counted by number. Handshakes aren't authenticated,
+/// so counting every version anyone sends would let them create as many series as they like.
+const KNOWN_PROTOCOL_VERSIONS: [i32; 34] = [
+    47, 107, 108, 109, 110, 210, 315, 316, 335, 338, 340, 393, 401, 404, 477, 480, 485, 490, 498, 573, 575, 578, 735,
+    736, 751, 753, 754, 755, 756, 757, 758, 759, 760, PROTOCOL_VERSION,
+];
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum ProtocolVersionLabel {
+    Known(i32),
+    Other,
+}
+
+impl std::fmt::Display for ProtocolVersionLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolVersionLabel::Known(version) => write!(f, "{}", version),
+            ProtocolVersionLabel::Other => f.write_str("other"),
+        }
+    }
+}
+
+/// Upper bounds of the chunk send latency histogram buckets, in seconds
+const CHUNK_SEND_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counters for the metrics endpoint, kept in the `ServerStore`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections: [AtomicI64; 4],
+    logins: Mutex<BTreeMap<&'static str, u64>>,
+    forwarding_failures: Mutex<BTreeMap<&'static str, u64>>,
+    disconnects: Mutex<BTreeMap<&'static str, u64>>,
+    protocol_versions: Mutex<BTreeMap<ProtocolVersionLabel, u64>>,
+    bytes_sent: Arc<AtomicU64>,
+    chunk_send_buckets: [AtomicU64; CHUNK_SEND_BUCKETS.len()],
+    chunk_send_count: AtomicU64,
+    /// In microseconds, so that it can be atomic
+    chunk_send_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts a new connection, which is tracked until the returned guard is dropped
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.connections[0].fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: Arc::clone(self),
+            state: ConnectionState::Handshake,
+        }
+    }
+
+    /// Moves a connection between states without a guard, for when the session registry takes over
+    pub fn connection_state_changed(&self, from: ConnectionState, to: ConnectionState) {
+        self.connection_count(from).fetch_sub(1, Ordering::Relaxed);
+        self.connection_count(to).fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn connection_count(&self, state: ConnectionState) -> &AtomicI64 {
+        let index = CONNECTION_STATES.iter().position(|s| *s == state).unwrap_or_default();
+        &self.connections[index]
+    }
+
+    pub fn record_login(&self, mode: ForwardingMode) {
+        increment(&self.logins, mode.name());
+    }
+
+    /// A proxy sent forwarding data that was missing, malformed, or signed with the wrong key or token
+    pub fn record_forwarding_failure(&self, mode: ForwardingMode) {
+        increment(&self.forwarding_failures, mode.name());
+    }
+
+    pub fn record_disconnect(&self, reason: &'static str) {
+        increment(&self.disconnects, reason);
+    }
+
+    /// Versions that aren't in `KNOWN_PROTOCOL_VERSIONS` are all counted together as `other`
+    pub fn record_protocol_version(&self, version: i32) {
+        let label = if KNOWN_PROTOCOL_VERSIONS.contains(&version) {
+            ProtocolVersionLabel::Known(version)
+        } else {
+            ProtocolVersionLabel::Other
+        };
+        increment(&self.protocol_versions, label);
+    }
+
+    /// How long it took to send a player the world's chunks
+    pub fn record_chunk_send(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in CHUNK_SEND_BUCKETS.iter().zip(&self.chunk_send_buckets) {
+            if seconds <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.chunk_send_count.fetch_add(1, Ordering::Relaxed);
+        self.chunk_send_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Wraps a writer so that everything written to it is counted
+    pub fn count_bytes_sent<W>(&self, inner: W) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            bytes_sent: Arc::clone(&self.bytes_sent),
+        }
+    }
+
+    /// Renders everything in the Prometheus text format
+    pub fn render(&self, store: &ServerStore) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "fallblock_connections", "gauge", "Open connections by protocol state");
+        for (state, count) in CONNECTION_STATES.iter().zip(&self.connections) {
+            let _ = writeln!(out, "fallblock_connections{{state=\"{}\"}} {}", state.name(), count.load(Ordering::Relaxed));
+        }
+
+        header(&mut out, "fallblock_play_sessions", "gauge", "Players in the session registry");
+        let _ = writeln!(out, "fallblock_play_sessions {}", store.online_player_count());
+
+        header(&mut out, "fallblock_queue_size", "gauge", "Players waiting to be sent to the upstream");
+        let _ = writeln!(out, "fallblock_queue_size {}", store.queue_len());
+
+        header(&mut out, "fallblock_logins_total", "counter", "Completed logins by forwarding mode");
+        labelled(&mut out, "fallblock_logins_total", "mode", &self.logins);
+
+        header(&mut out, "fallblock_forwarding_failures_total", "counter", "Logins rejected because of bad forwarding data");
+        labelled(&mut out, "fallblock_forwarding_failures_total", "mode", &self.forwarding_failures);
+
+        header(&mut out, "fallblock_disconnects_total", "counter", "Play and login connections ended, by reason");
+        labelled(&mut out, "fallblock_disconnects_total", "reason", &self.disconnects);
+
+        header(&mut out, "fallblock_protocol_versions_total", "counter", "Handshakes by protocol version");
+        labelled(&mut out, "fallblock_protocol_versions_total", "version", &self.protocol_versions);
+
+        header(&mut out, "fallblock_bytes_sent_total", "counter", "Bytes written to connections");
+        let _ = writeln!(out, "fallblock_bytes_sent_total {}", self.bytes_sent.load(Ordering::Relaxed));
+
+        header(&mut out, "fallblock_chunk_send_seconds", "histogram", "Time taken to send the world to a player");
+        for (bound, count) in CHUNK_SEND_BUCKETS.iter().zip(&self.chunk_send_buckets) {
+            let _ = writeln!(out, "fallblock_chunk_send_seconds_bucket{{le=\"{}\"}} {}", bound, count.load(Ordering::Relaxed));
+        }
+        let count = self.chunk_send_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "fallblock_chunk_send_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let sum = self.chunk_send_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "fallblock_chunk_send_seconds_sum {}", sum);
+        let _ = writeln!(out, "fallblock_chunk_send_seconds_count {}", count);
+
+        out
+    }
+}
+
+fn increment<K: Ord>(map: &Mutex<BTreeMap<K, u64>>, key: K) {
+    *map.lock().expect("metrics lock poisoned").entry(key).or_default() += 1;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn labelled<K: Ord + std::fmt::Display>(out: &mut String, name: &str, label: &str, map: &Mutex<BTreeMap<K, u64>>) {
+    for (key, count) in map.lock().expect("metrics lock poisoned").iter() {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, key, count);
+    }
+}
+
+/// Keeps a connection counted in its current state until dropped
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+    state: ConnectionState,
+}
+
+impl ConnectionGuard {
+    pub fn set_state(&mut self, state: ConnectionState) {
+        self.metrics.connection_state_changed(self.state, state);
+        self.state = state;
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.connection_count(self.state).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the bytes written through it towards `fallblock_bytes_sent_total`
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    bytes_sent: Arc<AtomicU64>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.bytes_sent.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Serves `GET /metrics` on the given address until the process exits
+pub async fn serve(store: ServerStore, address: SocketAddr) {
+    let make_service = make_service_fn(move |_| {
+        let store = store.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let store = store.clone();
+                async move { Ok::<_, Infallible>(handle_request(&store, request)) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("failed to start metrics server on {}: {}", address, e);
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics", address);
+    if let Err(e) = server.await {
+        error!("metrics server failed: {}", e);
+    }
+}
+
+fn handle_request(store: &ServerStore, request: Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().expect("valid content type"));
+            *response.body_mut() = Body::from(store.get_metrics().render(store));
+        }
+        _ => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, ProtocolVersionLabel};
+    use crate::constants::PROTOCOL_VERSION;
+
+    #[test]
+    fn counts_unknown_protocol_versions_together() {
+        let metrics = Metrics::default();
+        metrics.record_protocol_version(PROTOCOL_VERSION);
+        metrics.record_protocol_version(47);
+        for version in [-1, 0, 12345, i32::MAX] {
+            metrics.record_protocol_version(version);
+        }
+
+        let versions = metrics.protocol_versions.lock().unwrap();
+        let counted: Vec<_> = versions.iter().map(|(label, count)| (*label, *count)).collect();
+        assert_eq!(
+            counted,
+            [
+                (ProtocolVersionLabel::Known(47), 1),
+                (ProtocolVersionLabel::Known(PROTOCOL_VERSION), 1),
+                (ProtocolVersionLabel::Other, 4),
+            ]
+        );
+    }
+}
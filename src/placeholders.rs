@@ -0,0 +1,58 @@
+use mc_chat::{ChatComponent, ComponentType};
+
+use crate::{profile::Profile, store::ServerStore, util::format_duration};
+
+/// Values that can be substituted into configured messages, written as `{name}`
+#[derive(Clone, Debug)]
+pub struct Placeholders {
+    values: Vec<(&'static str, String)>,
+}
+
+impl Placeholders {
+    /// Creates the placeholders that are available everywhere
+    pub fn new(store: &ServerStore, profile: &Profile) -> Self {
+        let upstream = store.get_upstream_status();
+        Self {
+            values: vec![
+                ("online", store.online_player_count().to_string()),
+                ("max", store.max_players(profile).to_string()),
+                ("queue_size", store.queue_len().to_string()),
+                ("upstream_status", upstream.name().to_string()),
+                ("downtime", format_duration(upstream.downtime().unwrap_or_default())),
+                ("server_brand", profile.server_brand.clone()),
+            ],
+        }
+    }
+
+    /// Adds or replaces a placeholder
+    pub fn with(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.values.retain(|(n, _)| *n != name);
+        self.values.push((name, value.to_string()));
+        self
+    }
+
+    pub fn apply_str(&self, s: &str) -> String {
+        let mut s = s.to_string();
+        for (name, value) in &self.values {
+            s = s.replace(&format!("{{{}}}", name), value);
+        }
+        s
+    }
+
+    /// Substitutes placeholders in the text of a component and all of its children
+    pub fn apply(&self, component: &ChatComponent) -> ChatComponent {
+        let mut component = component.clone();
+        self.apply_in_place(&mut component);
+        component
+    }
+
+    fn apply_in_place(&self, component: &mut ChatComponent) {
+        if let ComponentType::Text(text) = component.get_kind_mut() {
+            let replaced = self.apply_str(text.get_text());
+            text.set_text(replaced);
+        }
+        for sibling in component.get_siblings_mut() {
+            self.apply_in_place(sibling);
+        }
+    }
+}
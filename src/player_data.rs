@@ -0,0 +1,216 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::interval;
+use uuid::Uuid;
+
+use crate::{
+    store::{Position, ServerStore},
+    util::Result,
+};
+
+/// How often changed player records are written back to disk
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What's remembered about a player between connections
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlayerRecord {
+    /// Unix timestamps, in seconds
+    pub first_seen: u64,
+    pub last_seen: u64,
+    #[serde(default)]
+    pub last_position: Option<SavedPosition>,
+}
+
+/// Where a player was when they last left, along with which map it was in
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedPosition {
+    pub map_file: PathBuf,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+/// Player records, kept in memory and written back to a JSON file by [`run_save`] and at shutdown
+#[derive(Debug)]
+pub struct PlayerData {
+    path: PathBuf,
+    records: Mutex<HashMap<Uuid, PlayerRecord>>,
+    /// Set whenever a record changes, and cleared once it's been written
+    dirty: AtomicBool,
+    /// Held while writing, so that an older snapshot can't be renamed over a newer one
+    write_lock: Mutex<()>,
+}
+
+impl PlayerData {
+    /// Loads the file at `path`, starting out empty if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let records = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            records: Mutex::new(records),
+            dirty: AtomicBool::new(false),
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Notes that a player has joined, returning where they were when they last left
+    pub fn record_join(&self, uuid: Uuid) -> Option<SavedPosition> {
+        let now = unix_time();
+        let last_position = {
+            let mut records = self.records.lock().expect("player data lock poisoned");
+            let record = records.entry(uuid).or_insert(PlayerRecord {
+                first_seen: now,
+                last_seen: now,
+                last_position: None,
+            });
+            record.last_seen = now;
+            record.last_position.clone()
+        };
+        self.dirty.store(true, Ordering::Relaxed);
+        last_position
+    }
+
+    /// Remembers where a player was in the given map when they left
+    pub fn record_leave(&self, uuid: Uuid, map_file: &Path, position: &Position) {
+        let now = unix_time();
+        {
+            let mut records = self.records.lock().expect("player data lock poisoned");
+            let record = records.entry(uuid).or_insert(PlayerRecord {
+                first_seen: now,
+                last_seen: now,
+                last_position: None,
+            });
+            record.last_seen = now;
+            record.last_position = Some(SavedPosition {
+                map_file: map_file.to_path_buf(),
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                yaw: position.yaw,
+                pitch: position.pitch,
+            });
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the records out if anything has changed since the last save.
+    /// Goes through a temporary file so that a crash can't leave it half written.
+    /// This blocks, so call it from a blocking task.
+    pub fn flush(&self) -> Result<()> {
+        let _write = self.write_lock.lock().expect("player data lock poisoned");
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let result = self.write();
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn write(&self) -> Result<()> {
+        let data = serde_json::to_vec(&*self.records.lock().expect("player data lock poisoned"))?;
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, &data)?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Periodically saves player data, if it's enabled
+pub async fn run_save(store: ServerStore) {
+    if store.get_player_data().is_none() {
+        return;
+    }
+
+    let mut save_interval = interval(SAVE_INTERVAL);
+    save_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick completes immediately, and there's nothing to save yet
+    save_interval.tick().await;
+
+    loop {
+        save_interval.tick().await;
+        save(&store).await;
+    }
+}
+
+/// Writes out any changed player data, logging rather than returning errors
+pub async fn save(store: &ServerStore) {
+    let store = store.clone();
+    let result = tokio::task::spawn_blocking(move || match store.get_player_data() {
+        Some(player_data) => player_data.flush(),
+        None => Ok(()),
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("failed to save player data: {}", e),
+        Err(e) => error!("player data save task failed: {}", e),
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position() -> Position {
+        Position {
+            x: 1.5,
+            y: 64.0,
+            z: -2.5,
+            yaw: 90.0,
+            pitch: 0.0,
+            on_ground: true,
+        }
+    }
+
+    #[test]
+    fn only_writes_when_flushed() {
+        let dir = std::env::temp_dir().join(format!("fallblock-player-data-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("players.json");
+        let _ = fs::remove_file(&path);
+        let uuid = Uuid::from_u128(1);
+
+        let player_data = PlayerData::load(&path).unwrap();
+        assert!(player_data.record_join(uuid).is_none());
+        player_data.record_leave(uuid, Path::new("world.nbt"), &position());
+        assert!(!path.exists());
+
+        player_data.flush().unwrap();
+        assert!(path.exists());
+        assert!(!path.with_extension("tmp").exists());
+
+        let saved = PlayerData::load(&path).unwrap().record_join(uuid).unwrap();
+        assert_eq!(saved.map_file, Path::new("world.nbt"));
+        assert_eq!((saved.x, saved.y, saved.z), (1.5, 64.0, -2.5));
+
+        // nothing changed, so the file is left alone
+        fs::remove_file(&path).unwrap();
+        player_data.flush().unwrap();
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
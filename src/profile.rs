@@ -0,0 +1,239 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{BoundsConfig, Config, MotdConfig, TabListConfig},
+    favicon::load_favicon,
+    protocol::{play::JoinGameData, status::ServerListPingResponse},
+    queue::QueueDisplayConfig,
+    util::Result,
+    world::World,
+};
+
+/// Overrides for players connecting through particular hostnames.
+/// Anything left out falls back to the top level config.
+#[derive(Debug, Default, Deserialize)]
+pub struct VirtualHostConfig {
+    /// Hostnames to match, where `*` matches any number of characters (ie. `*.example.com`)
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub server_brand: Option<String>,
+    #[serde(default)]
+    pub tab_list: Option<TabListConfig>,
+    #[serde(default)]
+    pub status: Option<ServerListPingResponse>,
+    #[serde(default)]
+    pub motd: Option<MotdConfig>,
+    #[serde(default)]
+    pub favicon_file: Option<PathBuf>,
+    #[serde(default)]
+    pub map_file: Option<PathBuf>,
+    #[serde(default)]
+    pub spawn_point: Option<(f64, f64, f64)>,
+    #[serde(default)]
+    pub bounds: Option<BoundsConfig>,
+    #[serde(default)]
+    pub join_game_data: Option<JoinGameData>,
+    #[serde(default)]
+    pub hide_players: Option<bool>,
+    #[serde(default)]
+    pub queue_display: Option<QueueDisplayConfig>,
+}
+
+/// Everything that can differ between virtual hosts, with the fallbacks already applied
+#[derive(Debug)]
+pub struct Profile {
+    /// Unique to each profile, so players can tell whether they're in the same world
+    pub id: usize,
+    pub server_brand: String,
+    pub tab_list: Option<TabListConfig>,
+    pub status: ServerListPingResponse,
+    pub motd: Option<MotdConfig>,
+    pub world: Arc<World>,
+    /// Where `world` was loaded from
+    pub map_file: PathBuf,
+    pub spawn_point: (f64, f64, f64),
+    pub bounds: BoundsConfig,
+    pub join_game_data: JoinGameData,
+    /// Stops players from seeing each other, even when the shared world is enabled
+    pub hide_players: bool,
+    pub queue_display: Option<QueueDisplayConfig>,
+}
+
+/// Profile ids carry on counting across config reloads, so that a profile whose world changed gets an id that's never been used
+static NEXT_PROFILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub struct Profiles {
+    default: Arc<Profile>,
+    virtual_hosts: Vec<(Vec<String>, Arc<Profile>)>,
+}
+
+impl Profiles {
+    /// Builds the profiles for a config. When reloading, the current profiles should be passed as `previous`,
+    /// so that profiles whose world hasn't changed keep their ids and players who join afterwards are still
+    /// in the same world as those who were already online.
+    pub fn load(config: &Config, previous: Option<&Profiles>) -> Result<Self> {
+        // Virtual hosts will often share a map, so only load each one once
+        let mut worlds = HashMap::new();
+        let mut load_world = |path: &PathBuf| -> Result<Arc<World>> {
+            if let Some(world) = worlds.get(path) {
+                return Ok(Arc::clone(world));
+            }
+            info!("Loading chunks from {}...", path.display());
+            let world = Arc::new(World::load(path)?);
+            worlds.insert(path.clone(), Arc::clone(&world));
+            Ok(world)
+        };
+
+        let mut status = config.status.clone();
+        if let Some(path) = &config.favicon_file {
+            status.set_favicon(load_favicon(path)?);
+        }
+        let world = load_world(&config.map_file)?;
+        let (id, world) = keep_id(previous.map(|p| &p.default), &config.map_file, world);
+        let default = Arc::new(Profile {
+            id,
+            server_brand: config.server_brand.clone(),
+            tab_list: config.tab_list.clone(),
+            status,
+            motd: config.motd.clone(),
+            world,
+            map_file: config.map_file.clone(),
+            spawn_point: config.spawn_point,
+            bounds: config.bounds.clone(),
+            join_game_data: config.join_game_data.clone(),
+            hide_players: config.hide_players,
+            queue_display: config.queue_display.clone(),
+        });
+
+        let mut virtual_hosts = Vec::with_capacity(config.virtual_hosts.len());
+        for host in &config.virtual_hosts {
+            let mut status = host.status.clone().unwrap_or_else(|| default.status.clone());
+            if let Some(path) = &host.favicon_file {
+                status.set_favicon(load_favicon(path)?);
+            }
+            // A virtual host with its own status uses its own description, rather than the default MOTDs
+            let motd = match (&host.motd, &host.status) {
+                (Some(motd), _) => Some(motd.clone()),
+                (None, Some(_)) => None,
+                (None, None) => default.motd.clone(),
+            };
+            let patterns: Vec<_> = host.hosts.iter().map(|h| h.to_lowercase()).collect();
+            let map_file = host.map_file.clone().unwrap_or_else(|| default.map_file.clone());
+            let world = match &host.map_file {
+                Some(path) => load_world(path)?,
+                None => Arc::clone(&default.world),
+            };
+            // Virtual hosts are told apart by their host patterns, so that adding or reordering them doesn't matter
+            let previous = previous.and_then(|p| p.virtual_hosts.iter().find(|(p, _)| *p == patterns));
+            let (id, world) = keep_id(previous.map(|(_, profile)| profile), &map_file, world);
+            let profile = Profile {
+                id,
+                server_brand: host.server_brand.clone().unwrap_or_else(|| default.server_brand.clone()),
+                tab_list: host.tab_list.clone().or_else(|| default.tab_list.clone()),
+                status,
+                motd,
+                world,
+                map_file,
+                spawn_point: host.spawn_point.unwrap_or(default.spawn_point),
+                bounds: host.bounds.clone().unwrap_or_else(|| default.bounds.clone()),
+                join_game_data: host.join_game_data.clone().unwrap_or_else(|| default.join_game_data.clone()),
+                hide_players: host.hide_players.unwrap_or(default.hide_players),
+                queue_display: host.queue_display.clone().or_else(|| default.queue_display.clone()),
+            };
+            virtual_hosts.push((patterns, Arc::new(profile)));
+        }
+
+        Ok(Self { default, virtual_hosts })
+    }
+
+    /// Picks the profile for a hostname, which should already have been through `util::normalize_server_address`.
+    /// Virtual hosts are checked in the order they're configured.
+    pub fn get(&self, host: &str) -> Arc<Profile> {
+        // No real hostname is this long, and the handshake allows much longer addresses for proxies' forwarding data
+        if host.len() > MAX_HOST_LENGTH {
+            return Arc::clone(&self.default);
+        }
+        self.virtual_hosts
+            .iter()
+            .find(|(patterns, _)| patterns.iter().any(|p| wildcard_match(p, host)))
+            .map(|(_, profile)| Arc::clone(profile))
+            .unwrap_or_else(|| Arc::clone(&self.default))
+    }
+}
+
+/// Reuses the previous profile's id and world if it had the same map, or gives out a new id if the world changed
+fn keep_id(previous: Option<&Arc<Profile>>, map_file: &Path, world: Arc<World>) -> (usize, Arc<World>) {
+    match previous {
+        Some(previous) if previous.map_file == map_file && previous.world == world => (previous.id, Arc::clone(&previous.world)),
+        _ => (NEXT_PROFILE_ID.fetch_add(1, Ordering::Relaxed), world),
+    }
+}
+
+/// The longest a DNS name can be
+const MAX_HOST_LENGTH: usize = 253;
+
+/// Glob matching that only ever backtracks to the latest `*`, which keeps it linear for typical patterns
+fn wildcard_match(pattern: &str, host: &str) -> bool {
+    let (pattern, host) = (pattern.as_bytes(), host.as_bytes());
+    let (mut p, mut h) = (0, 0);
+    // Where the latest `*` was in the pattern, and where in the host it started matching from
+    let mut star = None;
+    while h < host.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, h));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == host[h] {
+            p += 1;
+            h += 1;
+        } else if let Some((star_p, star_h)) = star {
+            // Let the `*` take one more byte and try again from there
+            star = Some((star_p, star_h + 1));
+            p = star_p + 1;
+            h = star_h + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|b| *b == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildcard_match;
+
+    #[test]
+    fn matches_exact_hosts() {
+        assert!(wildcard_match("play.example.com", "play.example.com"));
+        assert!(!wildcard_match("play.example.com", "example.com"));
+        assert!(!wildcard_match("example.com", "play.example.com"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("*.example.com", "play.example.com"));
+        assert!(wildcard_match("*.example.com", "a.b.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("b*e.test", "be.test"));
+        assert!(wildcard_match("b*e.test", "blue.test"));
+        assert!(!wildcard_match("b*e.test", "blue.tests"));
+        assert!(wildcard_match("*.*.com", "a.b.com"));
+        assert!(!wildcard_match("*.*.com", "ab.com"));
+        assert!(wildcard_match("**.com", "a.com"));
+        assert!(wildcard_match("a*", "a"));
+        assert!(!wildcard_match("a*b", "ab.c"));
+    }
+
+    #[test]
+    fn matches_long_hosts_quickly() {
+        // This took exponential time with recursive backtracking
+        let host = "a".repeat(32767);
+        let started = std::time::Instant::now();
+        assert!(!wildcard_match("*a*a*a*a*a*b", &host));
+        assert!(wildcard_match("*a*a*a*a*a*", &host));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
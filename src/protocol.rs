@@ -2,13 +2,16 @@ pub mod handshake;
 pub mod login;
 pub mod status;
 pub mod play;
+pub mod forwarding;
 
 use std::{io::{Cursor, Read}, fmt::LowerHex};
 
 use bytes::{BytesMut, Buf, Bytes};
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::{util::ProtocolError, io::{PacketReader, PacketWriter}};
+use crate::{util::ProtocolError, io::{PacketField, PacketReader, PacketWriter}, capture::{PacketRecorder, Direction}};
+
+pub use fallblock_derive::Packet;
 
 #[derive(Debug)]
 pub enum ProtocolState {
@@ -16,6 +19,40 @@ pub enum ProtocolState {
     Login,
 }
 
+impl PacketField for ProtocolState {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> crate::util::Result<Self> {
+        match rdr.read_var_int()? {
+            1 => Ok(ProtocolState::Status),
+            2 => Ok(ProtocolState::Login),
+            v => Err(ProtocolError::InvalidEnumValue(v)),
+        }
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> crate::util::Result<()> {
+        wr.write_var_int(match self {
+            ProtocolState::Status => 1,
+            ProtocolState::Login => 2,
+        })
+    }
+}
+
+/// A packet that can be sent or recieved in a given protocol state, usually implemented with `#[derive(Packet)]`.
+/// For enums, each variant is a different packet.
+pub trait Packet: Sized {
+    fn packet_id(&self) -> i32;
+
+    /// Reads a packet, returning `None` if the packet ID isn't known
+    fn read<R: PacketReader>(packet_id: i32, rdr: &mut R) -> crate::util::Result<Option<Self>>;
+
+    fn write_data<W: PacketWriter>(&self, wr: &mut W) -> crate::util::Result<()>;
+
+    fn write(&self) -> crate::util::Result<PacketPayload> {
+        let mut payload = PacketPayload::new(self.packet_id());
+        self.write_data(&mut payload)?;
+        Ok(payload)
+    }
+}
+
 // #region packet wrappers
 
 /// Wrapper for incoming packets
@@ -66,6 +103,10 @@ impl PacketReader for PacketData {
         self.data.read_string(max_len)
     }
 
+    fn read_bytes(&mut self, len: usize) -> crate::util::Result<Vec<u8>> {
+        self.data.read_bytes(len)
+    }
+
     // We implement this differently to the blanket impl as we can optimise the Vec
     // capcacity based on the actual amount of data we have.
     fn read_remaining(&mut self) -> crate::util::Result<Vec<u8>> {
@@ -153,7 +194,15 @@ impl PacketWriter for PacketPayload {
 
 // #endregion
 
-pub struct MinecraftFramedCodec;
+pub struct MinecraftFramedCodec {
+    recorder: Option<PacketRecorder>,
+}
+
+impl MinecraftFramedCodec {
+    pub fn new(recorder: Option<PacketRecorder>) -> Self {
+        Self { recorder }
+    }
+}
 
 impl Decoder for MinecraftFramedCodec {
     type Item = PacketData;
@@ -183,6 +232,9 @@ impl Decoder for MinecraftFramedCodec {
                     src.advance(i + 1 + length);
                     let mut data = Cursor::new(data);
                     let packet_id = data.read_var_int()?;
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(Direction::Serverbound, packet_id, &data.get_ref()[data.position() as usize..]);
+                    }
                     let data = PacketData {
                         packet_id,
                         data,
@@ -205,6 +257,10 @@ impl Encoder<PacketPayload> for MinecraftFramedCodec {
     type Error = ProtocolError;
 
     fn encode(&mut self, item: PacketPayload, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Clientbound, item.packet_id, &item.data);
+        }
+
         let mut id_data = Vec::with_capacity(5);
         id_data.write_var_int(item.packet_id)?;
 
@@ -223,3 +279,134 @@ impl Encoder<PacketPayload> for MinecraftFramedCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::protocol::{login::IncomingLoginPacket, play::PlayCustomPayload};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestData {
+        name: String,
+        count: i32,
+    }
+
+    #[derive(Debug, PartialEq, Packet)]
+    enum TestPacket {
+        #[packet(id = 0x00)]
+        Numbers {
+            #[var_int]
+            int: i32,
+            #[var_long]
+            long: i64,
+            plain: i32,
+        },
+        #[packet(id = 0x01)]
+        Text {
+            #[string(max = 4)]
+            short: String,
+            long: String,
+        },
+        #[packet(id = 0x02)]
+        Json {
+            #[json]
+            value: TestData,
+        },
+        #[packet(id = 0x03)]
+        Nbt {
+            #[nbt]
+            value: TestData,
+        },
+        #[packet(id = 0x04)]
+        Remaining {
+            kind: u8,
+            #[remaining]
+            data: Vec<u8>,
+        },
+    }
+
+    fn write<P: Packet>(packet: &P) -> Vec<u8> {
+        let mut data = Vec::new();
+        packet.write_data(&mut data).unwrap();
+        data
+    }
+
+    fn round_trip<P: Packet>(packet: &P) -> P {
+        P::read(packet.packet_id(), &mut &write(packet)[..]).unwrap().unwrap()
+    }
+
+    fn test_data() -> TestData {
+        TestData { name: "fallblock".into(), count: 42 }
+    }
+
+    #[test]
+    fn writes_var_ints_and_var_longs() {
+        let packet = TestPacket::Numbers { int: 300, long: -1, plain: 1 };
+        let mut expected = vec![0xac, 0x02];
+        expected.extend([0xff; 9]);
+        expected.push(0x01);
+        expected.extend([0, 0, 0, 1]);
+        assert_eq!(write(&packet), expected);
+        assert_eq!(round_trip(&packet), packet);
+    }
+
+    #[test]
+    fn limits_string_lengths() {
+        let packet = TestPacket::Text { short: "abcd".into(), long: "a".repeat(1000) };
+        assert_eq!(round_trip(&packet), packet);
+
+        let mut data = Vec::new();
+        data.write_string("abcde", 5).unwrap();
+        data.write_string("", 32767).unwrap();
+        assert!(matches!(TestPacket::read(0x01, &mut &data[..]), Err(ProtocolError::StringTooLong(5, 4))));
+    }
+
+    #[test]
+    fn round_trips_json_and_nbt() {
+        let packet = TestPacket::Json { value: test_data() };
+        assert_eq!(&write(&packet)[1..], br#"{"name":"fallblock","count":42}"#);
+        assert_eq!(round_trip(&packet), packet);
+
+        let packet = TestPacket::Nbt { value: test_data() };
+        assert_eq!(write(&packet)[0], 0x0a);
+        assert_eq!(round_trip(&packet), packet);
+    }
+
+    #[test]
+    fn reads_remaining_bytes() {
+        let packet = TestPacket::Remaining { kind: 7, data: vec![1, 2, 3, 4] };
+        assert_eq!(write(&packet), [7, 1, 2, 3, 4]);
+        assert_eq!(round_trip(&packet), packet);
+
+        let data = [0x05, 0x01, 0xaa, 0xbb];
+        match IncomingLoginPacket::read(0x02, &mut &data[..]).unwrap() {
+            Some(IncomingLoginPacket::LoginPluginResponse { message_id, successful, data }) => {
+                assert_eq!(message_id, 5);
+                assert!(successful);
+                assert_eq!(data, [0xaa, 0xbb]);
+            }
+            p => panic!("unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn ignores_unknown_packet_ids() {
+        assert!(TestPacket::read(0x05, &mut &[][..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_unknown_plugin_channels() {
+        let payload = PlayCustomPayload::Unknown { channel: "example:test".into(), data: vec![1, 2, 3] };
+        let mut data = Vec::new();
+        payload.write_field(&mut data).unwrap();
+        match PlayCustomPayload::read_field(&mut &data[..]).unwrap() {
+            PlayCustomPayload::Unknown { channel, data } => {
+                assert_eq!(channel, "example:test");
+                assert_eq!(data, [1, 2, 3]);
+            }
+            p => panic!("unexpected payload {:?}", p),
+        }
+    }
+}
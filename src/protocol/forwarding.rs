@@ -0,0 +1,323 @@
+use std::io::Cursor;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    io::{PacketField, PacketReader},
+    util::{self, ProtocolError, Result},
+};
+
+/// How the proxy in front of fallblock tells us who is connecting
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardingMode {
+    /// Players are given offline mode UUIDs
+    #[default]
+    None,
+    /// BungeeCord's legacy forwarding, where the player's details are appended to the handshake address
+    BungeeCord,
+    /// Legacy forwarding, with a token from `bungeeguard_tokens` to prove it came from our proxy
+    BungeeGuard,
+    /// Velocity's modern forwarding, signed with `modern_forwarding_key`
+    Velocity,
+}
+
+impl ForwardingMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ForwardingMode::None => "none",
+            ForwardingMode::BungeeCord => "bungeecord",
+            ForwardingMode::BungeeGuard => "bungeeguard",
+            ForwardingMode::Velocity => "velocity",
+        }
+    }
+}
+
+/// A game profile property, like the player's skin
+#[derive(Clone, Debug, Deserialize, Serialize, PacketField)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Player details sent by BungeeCord in the handshake's server address
+#[derive(Clone, Debug)]
+pub struct BungeeCordData {
+    pub host: String,
+    pub client_address: String,
+    pub uuid: Uuid,
+    pub properties: Vec<ProfileProperty>,
+}
+
+const BUNGEEGUARD_TOKEN_PROPERTY: &str = "bungeeguard-token";
+
+/// Parses a server address of the form `host\0client address\0uuid\0properties`.
+/// The properties may be missing, and anything after them (like Forge's markers) is ignored.
+pub fn parse_bungeecord(server_address: &str) -> Result<BungeeCordData> {
+    let mut parts = server_address.split('\0');
+    let (host, client_address, uuid) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(host), Some(client_address), Some(uuid)) => (host, client_address, uuid),
+        _ => return Err(ProtocolError::InvalidForwarding("missing BungeeCord forwarding data".into())),
+    };
+    let uuid = Uuid::parse_str(uuid)
+        .map_err(|e| ProtocolError::InvalidForwarding(format!("invalid uuid {:?}: {}", uuid, e)))?;
+    let properties = match parts.next() {
+        Some(properties) if properties.starts_with('[') => serde_json::from_str(properties)?,
+        _ => Vec::new(),
+    };
+
+    Ok(BungeeCordData {
+        host: host.to_string(),
+        client_address: client_address.to_string(),
+        uuid,
+        properties,
+    })
+}
+
+/// Checks that exactly one BungeeGuard token was forwarded and that it's one we accept,
+/// removing it from the properties so it doesn't get sent on to other players
+pub fn take_bungeeguard_token(properties: &mut Vec<ProfileProperty>, tokens: &[String]) -> bool {
+    let (token, rest): (Vec<_>, Vec<_>) = properties
+        .drain(..)
+        .partition(|p| p.name == BUNGEEGUARD_TOKEN_PROPERTY);
+    *properties = rest;
+    match &token[..] {
+        // check every token, so the time taken doesn't give away which one was close
+        [token] => tokens
+            .iter()
+            .fold(false, |found, t| util::constant_time_eq(t.as_bytes(), token.value.as_bytes()) | found),
+        _ => false,
+    }
+}
+
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+
+/// The original forwarding format, and the only one Velocity will send for 1.18.2 players
+pub const VELOCITY_DEFAULT_VERSION: u8 = 1;
+/// Adds the player's chat signing key
+pub const VELOCITY_WITH_KEY_VERSION: u8 = 2;
+/// Adds the UUID the chat signing key belongs to
+pub const VELOCITY_WITH_KEY_V2_VERSION: u8 = 3;
+/// Drops the chat signing key again, as it's sent later on in the play state instead
+pub const VELOCITY_LAZY_SESSION_VERSION: u8 = 4;
+/// The newest forwarding version we understand, which is sent to Velocity in the login plugin request
+pub const VELOCITY_MAX_VERSION: u8 = VELOCITY_LAZY_SESSION_VERSION;
+
+const VELOCITY_SIGNATURE_LENGTH: usize = 32;
+
+/// Player details sent by Velocity in response to a `velocity:player_info` login plugin request
+#[derive(Clone, Debug)]
+pub struct VelocityData {
+    pub version: u8,
+    pub client_address: String,
+    pub uuid: Uuid,
+    pub username: String,
+    pub properties: Vec<ProfileProperty>,
+    pub player_key: Option<PlayerKey>,
+}
+
+/// A player's chat signing key, only forwarded by versions 2 and 3
+#[derive(Clone, Debug)]
+pub struct PlayerKey {
+    pub expires_at: i64,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    /// Which player the key was issued to (version 3 only)
+    pub holder: Option<Uuid>,
+}
+
+/// Checks the signature on a `velocity:player_info` response and parses the player details in it
+pub fn parse_velocity(key: &[u8], data: &[u8]) -> Result<VelocityData> {
+    if data.len() < VELOCITY_SIGNATURE_LENGTH {
+        return Err(ProtocolError::InvalidForwarding(format!(
+            "velocity response is {} bytes, which is too short to be signed",
+            data.len()
+        )));
+    }
+    let (signature, payload) = data.split_at(VELOCITY_SIGNATURE_LENGTH);
+    if !check_signature(key, signature, payload) {
+        return Err(ProtocolError::InvalidForwarding(
+            "velocity response has an invalid signature, check that modern_forwarding_key matches the proxy's secret".into(),
+        ));
+    }
+
+    let rdr = &mut Cursor::new(payload);
+    let version = rdr.read_var_int()?;
+    let version = match u8::try_from(version) {
+        Ok(version) if (VELOCITY_DEFAULT_VERSION..=VELOCITY_MAX_VERSION).contains(&version) => version,
+        _ => {
+            return Err(ProtocolError::InvalidForwarding(format!(
+                "unsupported velocity forwarding version {} (max: {})",
+                version, VELOCITY_MAX_VERSION
+            )))
+        }
+    };
+    let client_address = rdr.read_string(32767)?;
+    let uuid = rdr.read_uuid()?;
+    let username = rdr.read_string(16)?;
+    let properties = Vec::<ProfileProperty>::read_field(rdr)?;
+    let player_key = if (VELOCITY_WITH_KEY_VERSION..VELOCITY_LAZY_SESSION_VERSION).contains(&version) {
+        let expires_at = rdr.read_long()?;
+        let public_key = read_byte_array(rdr)?;
+        let signature = read_byte_array(rdr)?;
+        let holder = if version >= VELOCITY_WITH_KEY_V2_VERSION {
+            Option::<Uuid>::read_field(rdr)?
+        } else {
+            None
+        };
+        Some(PlayerKey {
+            expires_at,
+            public_key,
+            signature,
+            holder,
+        })
+    } else {
+        None
+    };
+
+    Ok(VelocityData {
+        version,
+        client_address,
+        uuid,
+        username,
+        properties,
+        player_key,
+    })
+}
+
+/// Keys and signatures are a few hundred bytes, so anything bigger than this is garbage
+const MAX_BYTE_ARRAY_LENGTH: i32 = 4096;
+
+fn read_byte_array<R: PacketReader>(rdr: &mut R) -> Result<Vec<u8>> {
+    let len = rdr.read_var_int()?;
+    if !(0..=MAX_BYTE_ARRAY_LENGTH).contains(&len) {
+        return Err(ProtocolError::InvalidForwarding(format!("invalid byte array length {}", len)));
+    }
+    rdr.read_bytes(len as usize)
+}
+
+fn check_signature(key: &[u8], sig: &[u8], payload: &[u8]) -> bool {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC can take a key of any size");
+    mac.update(payload);
+    mac.verify_slice(sig).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::io::PacketWriter;
+
+    const KEY: &[u8] = b"secret";
+
+    fn payload(version: u8) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.write_var_int(version as i32).unwrap();
+        payload.write_string("127.0.0.1", 32767).unwrap();
+        payload.write_uuid(&Uuid::from_u128(1)).unwrap();
+        payload.write_string("Player", 16).unwrap();
+        payload.write_var_int(1).unwrap();
+        payload.write_string("textures", 32767).unwrap();
+        payload.write_string("value", 32767).unwrap();
+        payload.write_bool(false).unwrap();
+        if version == VELOCITY_WITH_KEY_VERSION || version == VELOCITY_WITH_KEY_V2_VERSION {
+            payload.write_long(1234).unwrap();
+            payload.write_var_int(3).unwrap();
+            payload.write_bytes(&[1, 2, 3]).unwrap();
+            payload.write_var_int(2).unwrap();
+            payload.write_bytes(&[4, 5]).unwrap();
+            if version == VELOCITY_WITH_KEY_V2_VERSION {
+                payload.write_bool(true).unwrap();
+                payload.write_uuid(&Uuid::from_u128(2)).unwrap();
+            }
+        }
+        payload
+    }
+
+    fn sign(key: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(payload);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn parses_every_velocity_version() {
+        for version in VELOCITY_DEFAULT_VERSION..=VELOCITY_MAX_VERSION {
+            let data = parse_velocity(KEY, &sign(KEY, &payload(version))).unwrap();
+            assert_eq!(data.version, version);
+            assert_eq!(data.client_address, "127.0.0.1");
+            assert_eq!(data.uuid, Uuid::from_u128(1));
+            assert_eq!(data.username, "Player");
+            assert_eq!(data.properties.len(), 1);
+            assert_eq!(data.properties[0].name, "textures");
+            assert_eq!(data.properties[0].signature, None);
+
+            match version {
+                VELOCITY_WITH_KEY_VERSION | VELOCITY_WITH_KEY_V2_VERSION => {
+                    let key = data.player_key.unwrap();
+                    assert_eq!(key.expires_at, 1234);
+                    assert_eq!(key.public_key, [1, 2, 3]);
+                    assert_eq!(key.signature, [4, 5]);
+                    let holder = (version == VELOCITY_WITH_KEY_V2_VERSION).then(|| Uuid::from_u128(2));
+                    assert_eq!(key.holder, holder);
+                }
+                _ => assert!(data.player_key.is_none()),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_signatures() {
+        let payload = payload(VELOCITY_DEFAULT_VERSION);
+        assert!(parse_velocity(KEY, &sign(b"wrong", &payload)).is_err());
+
+        let mut tampered = sign(KEY, &payload);
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(parse_velocity(KEY, &tampered).is_err());
+
+        assert!(parse_velocity(KEY, &[0; 8]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(parse_velocity(KEY, &sign(KEY, &payload(0))).is_err());
+        assert!(parse_velocity(KEY, &sign(KEY, &payload(VELOCITY_MAX_VERSION + 1))).is_err());
+    }
+
+    fn property(name: &str, value: &str) -> ProfileProperty {
+        ProfileProperty {
+            name: name.into(),
+            value: value.into(),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn checks_bungeeguard_tokens() {
+        let tokens = ["first".to_string(), "second".to_string()];
+
+        let mut properties = vec![property("textures", "skin"), property(BUNGEEGUARD_TOKEN_PROPERTY, "second")];
+        assert!(take_bungeeguard_token(&mut properties, &tokens));
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0].name, "textures");
+
+        let mut properties = vec![property(BUNGEEGUARD_TOKEN_PROPERTY, "secon")];
+        assert!(!take_bungeeguard_token(&mut properties, &tokens));
+        assert!(properties.is_empty());
+
+        let mut properties = vec![property(BUNGEEGUARD_TOKEN_PROPERTY, "first"), property(BUNGEEGUARD_TOKEN_PROPERTY, "second")];
+        assert!(!take_bungeeguard_token(&mut properties, &tokens));
+        assert!(!take_bungeeguard_token(&mut Vec::new(), &tokens));
+    }
+}
@@ -1,107 +1,93 @@
-use std::io::Cursor;
+use std::sync::Arc;
 
 use futures::{TryStream, TryStreamExt, Sink, SinkExt};
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use mc_chat::{ChatComponent, ComponentStyle};
 use uuid::Uuid;
 
-use crate::{io::{PacketReader, PacketWriter}, util::{Result, ProtocolError, self}, store::ServerStore, protocol::play};
+use crate::{util::{Result, ProtocolError, self}, store::{ConnectionInfo, OnlinePlayer, ServerStore}, placeholders::Placeholders, protocol::{play, forwarding::{self, ForwardingMode, ProfileProperty}}, profile::Profile};
 
-use super::{PacketData, PacketPayload};
+use super::{Packet, PacketData, PacketPayload};
 
+#[derive(Debug, Packet)]
 pub enum IncomingLoginPacket {
+    #[packet(id = 0x00)]
     LoginStart {
+        #[string(max = 16)]
         username: String,
     },
+    #[packet(id = 0x02)]
     LoginPluginResponse {
+        #[var_int]
         message_id: i32,
         successful: bool,
+        #[remaining]
         data: Vec<u8>,
     },
 }
 
-impl IncomingLoginPacket {
-    pub fn read<R: PacketReader>(packet_id: i32, rdr: &mut R) -> Result<Self> {
-        match packet_id {
-            0 => Self::read_login_start(rdr),
-            2 => Self::read_login_plugin_response(rdr),
-            v => {
-                debug!(%v, "invalid packet id");
-                Err(ProtocolError::InvalidPacketId(v))
-            },
-        }
-    }
-
-    fn read_login_start<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::LoginStart {
-            username: rdr.read_string(16)?,
-        })
-    }
-
-    fn read_login_plugin_response<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        let message_id = rdr.read_var_int()?;
-        let successful = rdr.read_bool()?;
-        let data = if successful {
-            rdr.read_remaining()?
-        } else {
-            vec![]
-        };
-        Ok(Self::LoginPluginResponse {
-            message_id,
-            successful,
-            data,
-        })
-    }
-}
-
+#[derive(Debug, Packet)]
 pub enum OutgoingLoginPacket {
+    #[packet(id = 0x00)]
+    Disconnect {
+        #[json]
+        reason: ChatComponent,
+    },
+    #[packet(id = 0x02)]
     LoginSuccess {
         uuid: Uuid,
+        #[string(max = 16)]
         username: String,
     },
+    #[packet(id = 0x04)]
     LoginPluginRequest {
+        #[var_int]
         message_id: i32,
         channel: String,
+        #[remaining]
         data: Vec<u8>,
     },
 }
 
-impl OutgoingLoginPacket {
-    pub fn write(&self) -> Result<PacketPayload> {
-        let mut payload = PacketPayload::new(self.packet_id());
-        match self {
-            OutgoingLoginPacket::LoginSuccess { uuid, username } => {
-                payload.write_uuid(uuid)?;
-                payload.write_string(&username, 16)?;
-            },
-            OutgoingLoginPacket::LoginPluginRequest { message_id, channel, data } => {
-                payload.write_var_int(*message_id)?;
-                payload.write_string(&channel, 32767)?;
-                payload.write_bytes(&*data)?;
-            },
-        }
-        Ok(payload)
-    }
+/// Who a player is, once login or forwarding has worked it out
+struct PlayerIdentity {
+    uuid: Uuid,
+    username: String,
+    properties: Vec<ProfileProperty>,
+}
 
-    fn packet_id(&self) -> i32 {
-        match self {
-            OutgoingLoginPacket::LoginSuccess { .. } => 0x02,
-            OutgoingLoginPacket::LoginPluginRequest { .. } => 0x04,
-        }
-    }
+fn read_login_packet(packet: &mut PacketData) -> Result<IncomingLoginPacket> {
+    IncomingLoginPacket::read(packet.packet_id, packet)?.ok_or_else(|| {
+        debug!(v = %packet.packet_id, "invalid packet id");
+        ProtocolError::InvalidPacketId(packet.packet_id)
+    })
 }
 
 pub async fn handle<
     R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin,
     W: Sink<PacketPayload, Error = ProtocolError> + Unpin,
->(rdr: &mut R, wr: &mut W, store: ServerStore) -> Result<()> {
+>(
+    rdr: &mut R,
+    wr: &mut W,
+    server_address: &str,
+    connection: ConnectionInfo,
+    profile: Arc<Profile>,
+    store: ServerStore,
+) -> Result<()> {
     if let Some(mut packet) = rdr.try_next().await? {
-        if let IncomingLoginPacket::LoginStart { username } = IncomingLoginPacket::read(packet.packet_id, &mut packet)? {
-            return if store.get_config().modern_forwarding_key.is_some() {
-                modern_forwarding_handshake(rdr, wr, store, username).await
-            } else {
-                let uuid = util::offline_mode_uuid(&username);
-                complete_login(rdr, wr, store, uuid, username).await
+        if let IncomingLoginPacket::LoginStart { username } = read_login_packet(&mut packet)? {
+            return match store.get_config().forwarding_mode {
+                ForwardingMode::None => {
+                    let identity = PlayerIdentity {
+                        uuid: util::offline_mode_uuid(&username),
+                        username,
+                        properties: Vec::new(),
+                    };
+                    complete_login(rdr, wr, connection, profile, store, identity).await
+                }
+                ForwardingMode::BungeeCord | ForwardingMode::BungeeGuard => {
+                    bungeecord_login(rdr, wr, server_address, connection, profile, store, username).await
+                }
+                ForwardingMode::Velocity => modern_forwarding_handshake(rdr, wr, connection, profile, store, username).await,
             };
         }
     }
@@ -109,41 +95,112 @@ pub async fn handle<
     Ok(())
 }
 
+async fn bungeecord_login<
+    R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin,
+    W: Sink<PacketPayload, Error = ProtocolError> + Unpin,
+>(
+    rdr: &mut R,
+    wr: &mut W,
+    server_address: &str,
+    mut connection: ConnectionInfo,
+    profile: Arc<Profile>,
+    store: ServerStore,
+    username: String,
+) -> Result<()> {
+    let mut data = match forwarding::parse_bungeecord(server_address) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!(%username, "failed to perform legacy player forwarding: {}", e);
+            // Same message as the vanilla server, since it's usually a proxy misconfiguration
+            return forwarding_failed(wr, &store, "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!").await;
+        }
+    };
+    if store.get_config().forwarding_mode == ForwardingMode::BungeeGuard
+        && !forwarding::take_bungeeguard_token(&mut data.properties, &store.get_config().bungeeguard_tokens)
+    {
+        warn!(%username, client_address = %data.client_address, "missing or invalid BungeeGuard token");
+        return forwarding_failed(wr, &store, "Invalid forwarding token").await;
+    }
+    debug!(host = %data.host, client_address = %data.client_address, uuid = %data.uuid, %username, "completed legacy forwarding");
+    if let Ok(address) = data.client_address.parse() {
+        connection.address = address;
+    }
+    let identity = PlayerIdentity {
+        uuid: data.uuid,
+        username,
+        properties: data.properties,
+    };
+    complete_login(rdr, wr, connection, profile, store, identity).await
+}
+
+/// Disconnects a player whose forwarding data couldn't be used, counting it in the metrics
+async fn forwarding_failed<W: Sink<PacketPayload, Error = ProtocolError> + Unpin>(
+    wr: &mut W,
+    store: &ServerStore,
+    message: &str,
+) -> Result<()> {
+    let metrics = store.get_metrics();
+    metrics.record_forwarding_failure(store.get_config().forwarding_mode);
+    metrics.record_disconnect("forwarding_failed");
+    let reason = ChatComponent::from_text(message, ComponentStyle::v1_16());
+    wr.send(OutgoingLoginPacket::Disconnect { reason }.write()?).await
+}
+
 async fn modern_forwarding_handshake<
     R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin,
     W: Sink<PacketPayload, Error = ProtocolError> + Unpin,
->(rdr: &mut R, wr: &mut W, store: ServerStore, username: String) -> Result<()> {
+>(
+    rdr: &mut R,
+    wr: &mut W,
+    mut connection: ConnectionInfo,
+    profile: Arc<Profile>,
+    store: ServerStore,
+    username: String,
+) -> Result<()> {
     debug!("Performing modern forwarding handshake with user: {}", username);
     wr.send(OutgoingLoginPacket::LoginPluginRequest {
         message_id: 0x01,
-        channel: "velocity:player_info".into(),
-        data: vec![],
+        channel: forwarding::VELOCITY_CHANNEL.into(),
+        data: vec![forwarding::VELOCITY_MAX_VERSION],
     }.write()?).await?;
     if let Some(mut packet) = rdr.try_next().await? {
-        if let IncomingLoginPacket::LoginPluginResponse { message_id, successful, data } = IncomingLoginPacket::read(packet.packet_id, &mut packet)? {
+        if let IncomingLoginPacket::LoginPluginResponse { message_id, successful, data } = read_login_packet(&mut packet)? {
             if !successful {
                 warn!(?packet, "failed to perform modern player forwarding: not supported by client");
-                return Ok(());
+                return forwarding_failed(wr, &store, "This server requires you to connect with Velocity.").await;
             }
             if message_id == 0x01 {
                 // we got a response!
-                debug!(?data, "got a forwarding data data data");
-                let (sig, payload) = (&data[..32], &data[32..]);
-                let modern_forwarding_key = store
-                    .get_config()
+                let config = store.get_config();
+                let modern_forwarding_key = config
                     .modern_forwarding_key
                     .as_ref()
                     .expect("called modern_forwarding_handshake when modern forwarding is disabled");
-                if !check_signature(modern_forwarding_key.as_bytes(), sig, payload) {
-                    warn!(%username, ?packet, "modern forwarding information has invalid signature");
-                } else {
-                    let mut payload = Cursor::new(payload);
-                    let forwarding_version = payload.read_var_int()?;
-                    let client_address = payload.read_string(32767)?;
-                    let uuid = payload.read_uuid()?;
-                    let username = payload.read_string(16)?;
-                    debug!(%forwarding_version, %client_address, %uuid, %username, "completed modern information handshake");
-                    complete_login(rdr, wr, store, uuid, username).await?;
+                match forwarding::parse_velocity(modern_forwarding_key.as_bytes(), &data) {
+                    Ok(player) => {
+                        debug!(
+                            version = %player.version,
+                            client_address = %player.client_address,
+                            uuid = %player.uuid,
+                            username = %player.username,
+                            properties = player.properties.len(),
+                            has_key = player.player_key.is_some(),
+                            "completed modern information handshake"
+                        );
+                        if let Ok(address) = player.client_address.parse() {
+                            connection.address = address;
+                        }
+                        let identity = PlayerIdentity {
+                            uuid: player.uuid,
+                            username: player.username,
+                            properties: player.properties,
+                        };
+                        complete_login(rdr, wr, connection, profile, store, identity).await?;
+                    }
+                    Err(e) => {
+                        warn!(%username, "failed to perform modern player forwarding: {}", e);
+                        return forwarding_failed(wr, &store, "Unable to verify player details").await;
+                    }
                 }
             } else {
                 warn!(?packet, "got unknown plugin response");
@@ -157,20 +214,59 @@ async fn modern_forwarding_handshake<
 async fn complete_login<
     R: TryStream<Ok = PacketData, Error = ProtocolError> + Unpin,
     W: Sink<PacketPayload, Error = ProtocolError> + Unpin,
->(rdr: &mut R, wr: &mut W, store: ServerStore, uuid: Uuid, username: String)  -> Result<()> {
-    info!(%username, %uuid, "completing login");
+>(
+    rdr: &mut R,
+    wr: &mut W,
+    connection: ConnectionInfo,
+    profile: Arc<Profile>,
+    store: ServerStore,
+    identity: PlayerIdentity,
+) -> Result<()> {
+    let PlayerIdentity { uuid, username, properties } = identity;
+    if store.is_maintenance() {
+        info!(%username, %uuid, "in maintenance mode, disconnecting");
+        store.get_metrics().record_disconnect("maintenance");
+        let reason = store.get_config().maintenance_message.clone()
+            .unwrap_or_else(|| ChatComponent::from_text("The server is down for maintenance", ComponentStyle::v1_16()));
+        wr.send(OutgoingLoginPacket::Disconnect { reason }.write()?).await?;
+        return Ok(());
+    }
+    info!(%username, %uuid, address = %connection.address, "completing login");
+    let display_name = store.get_config().players.display_name.as_ref().map(|display_name| {
+        Placeholders::new(&store, &profile).with("username", &username).apply(display_name)
+    });
+    let player = OnlinePlayer {
+        uuid,
+        username: username.clone(),
+        properties,
+        gamemode: profile.join_game_data.gamemode(),
+        latency: 0,
+        display_name,
+        entity_id: 0,
+        profile_id: profile.id,
+        position: None,
+    };
+    let (session, messages) = match store.try_register_session(player, connection) {
+        Some(session) => session,
+        None => {
+            info!(%username, %uuid, "server is full, disconnecting");
+            store.get_metrics().record_disconnect("server_full");
+            let reason = store.get_config().players.full_message.clone()
+                .unwrap_or_else(|| ChatComponent::from_text("The server is full!", ComponentStyle::v1_16()));
+            wr.send(OutgoingLoginPacket::Disconnect { reason }.write()?).await?;
+            return Ok(());
+        }
+    };
+    store.get_metrics().record_login(store.get_config().forwarding_mode);
     let success_packet = OutgoingLoginPacket::LoginSuccess {
         uuid,
         username,
     }.write()?;
     wr.send(success_packet).await?;
-    play::handle(rdr, wr, uuid, store).await
-}
-
-fn check_signature(key: &[u8], sig: &[u8], payload: &[u8]) -> bool {
-    let mut mac = Hmac::<Sha256>::new_from_slice(key)
-        .expect("HMAC can take a key of any size");
-    mac.update(payload);
-    let result = mac.finalize().into_bytes();
-    &result[..] == sig
+    let result = play::handle(rdr, wr, uuid, Arc::clone(&profile), &session, messages, store.clone()).await;
+    // Saved even if the connection ended with an error, since that's usually just the client going away
+    if let (Some(player_data), Some(position)) = (store.get_player_data(), session.position()) {
+        player_data.record_leave(uuid, &profile.map_file, &position);
+    }
+    result
 }
@@ -1,48 +1,87 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{io::Cursor, sync::Arc, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
 use futures::{Sink, SinkExt, TryStream, TryStreamExt};
+use mc_chat::{ChatComponent, ComponentStyle};
 use serde::Deserialize;
-use tokio::time::interval;
+use tokio::{sync::{broadcast::error::RecvError, mpsc}, time::{interval, sleep_until}};
 use uuid::Uuid;
 
 use crate::{
+    chat::{ChatOutcome, ChatSession},
+    commands::{self, CommandAction},
     constants::Gamemode,
-    io::{PacketReader, PacketWriter},
-    store::ServerStore,
+    io::{PacketField, PacketReader, PacketWriter},
+    profile::Profile,
+    store::{Position, ServerStore, SessionGuard, SessionMessage},
     util::{ProtocolError, Result},
     world::{
-        chunk::{Chunk, Heightmaps},
-        dimension::{DimensionCodec, DimensionType}, map_template::BlockEntity, block_ids,
+        chunk::{Chunk, LightData},
+        dimension::{DimensionCodec, DimensionType}, map_template::BlockEntity,
     },
 };
 
-use super::{PacketData, PacketPayload};
+use self::{
+    announcer::Announcer,
+    boss_bar::BossBarAction,
+    command_graph::CommandNode,
+    movement::{MoveCheck, Teleports},
+    player_info::{ListedPlayers, PlayerInfo},
+    queue_display::QueueDisplay,
+    shared_world::{EntityIds, SharedWorld},
+    tab_list::TabList,
+};
+
+use super::{Packet, PacketData, PacketPayload};
+
+pub mod announcer;
+pub mod boss_bar;
+pub mod command_graph;
+pub mod movement;
+pub mod player_info;
+pub mod queue_display;
+pub mod shared_world;
+pub mod tab_list;
 
 // TODO: This file should probably be split up a bit.
 
-#[derive(Debug)]
+#[derive(Debug, Packet)]
 pub enum IncomingPlayPacket {
+    #[packet(id = 0x00)]
     TeleportConfirm {
+        #[var_int]
         teleport_id: i32,
     },
+    #[packet(id = 0x03)]
+    ChatMessage {
+        #[string(max = 256)]
+        message: String,
+    },
+    #[packet(id = 0x05)]
     ClientSettings {
+        #[string(max = 16)]
         locale: String,
         view_distance: i8,
+        #[var_int]
         chat_mode: i32,
         chat_colours: bool,
         displayed_skin_parts: i8,
+        #[var_int]
         main_hand: i32,
         enable_text_filtering: bool,
         allow_server_listings: bool,
     },
+    #[packet(id = 0x0A)]
     CustomPayload(PlayCustomPayload),
+    #[packet(id = 0x0F)]
     KeepAlive(i64),
+    #[packet(id = 0x11)]
     PlayerPosition {
         x: f64,
         y: f64,
         z: f64,
         on_ground: bool,
     },
+    #[packet(id = 0x12)]
     PlayerPositionAndRotation {
         x: f64,
         y: f64,
@@ -51,6 +90,7 @@ pub enum IncomingPlayPacket {
         pitch: f32,
         on_ground: bool,
     },
+    #[packet(id = 0x13)]
     PlayerRotation {
         yaw: f32,
         pitch: f32,
@@ -58,84 +98,68 @@ pub enum IncomingPlayPacket {
     },
 }
 
-impl IncomingPlayPacket {
-    pub fn read<R: PacketReader>(packet_id: i32, rdr: &mut R) -> Result<Option<Self>> {
-        match packet_id {
-            0x00 => Ok(Some(Self::read_teleport_confirm(rdr)?)),
-            0x05 => Ok(Some(Self::read_client_settings(rdr)?)),
-            0x0A => Ok(PlayCustomPayload::read(rdr)?.map(|p| Self::CustomPayload(p))),
-            0x0F => Ok(Some(Self::KeepAlive(rdr.read_long()?))),
-            0x11 => Ok(Some(Self::read_player_position(rdr)?)),
-            0x12 => Ok(Some(Self::read_player_position_and_rotation(rdr)?)),
-            0x13 => Ok(Some(Self::read_player_rotation(rdr)?)),
-            _ => Ok(None),
-        }
-    }
-
-    fn read_teleport_confirm<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::TeleportConfirm {
-             teleport_id: rdr.read_var_int()?,
-        })
-    }
-
-    fn read_client_settings<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::ClientSettings {
-            locale: rdr.read_string(16)?,
-            view_distance: rdr.read_byte()?,
-            chat_mode: rdr.read_var_int()?,
-            chat_colours: rdr.read_bool()?,
-            displayed_skin_parts: rdr.read_byte()?,
-            main_hand: rdr.read_var_int()?,
-            enable_text_filtering: rdr.read_bool()?,
-            allow_server_listings: rdr.read_bool()?,
-        })
-    }
-
-    fn read_player_position<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::PlayerPosition {
-            x: rdr.read_double()?,
-            y: rdr.read_double()?,
-            z: rdr.read_double()?,
-            on_ground: rdr.read_bool()?,
-        })
-    }
-
-    fn read_player_position_and_rotation<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::PlayerPositionAndRotation {
-            x: rdr.read_double()?,
-            y: rdr.read_double()?,
-            z: rdr.read_double()?,
-            yaw: rdr.read_float()?,
-            pitch: rdr.read_float()?,
-            on_ground: rdr.read_bool()?,
-        })
-    }
-
-    fn read_player_rotation<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::PlayerRotation {
-            yaw: rdr.read_float()?,
-            pitch: rdr.read_float()?,
-            on_ground: rdr.read_bool()?,
-        })
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Packet)]
 pub enum OutgoingPlayPacket {
+    #[packet(id = 0x04)]
+    SpawnPlayer {
+        #[var_int]
+        entity_id: i32,
+        uuid: Uuid,
+        x: f64,
+        y: f64,
+        z: f64,
+        yaw: u8,
+        pitch: u8,
+    },
+    #[packet(id = 0x0a)]
     BlockEntityData(BlockEntity),
+    #[packet(id = 0x0D)]
+    BossBar {
+        uuid: Uuid,
+        action: BossBarAction,
+    },
+    #[packet(id = 0x0F)]
+    ChatMessage {
+        #[json]
+        message: ChatComponent,
+        position: ChatPosition,
+        sender: Uuid,
+    },
+    #[packet(id = 0x12)]
+    DeclareCommands {
+        nodes: Vec<CommandNode>,
+        #[var_int]
+        root_index: i32,
+    },
+    #[packet(id = 0x18)]
     CustomPayload(PlayCustomPayload),
+    #[packet(id = 0x1A)]
+    Disconnect {
+        #[json]
+        reason: ChatComponent,
+    },
+    #[packet(id = 0x21)]
     KeepAlive(u64),
+    #[packet(id = 0x22)]
     ChunkData {
         chunk: Chunk,
     },
+    #[packet(id = 0x25)]
     UpdateLight {
+        #[var_int]
         chunk_x: i32,
+        #[var_int]
         chunk_z: i32,
+        light: LightData,
     },
+    #[packet(id = 0x26)]
     JoinGame {
         entity_id: i32,
         data: JoinGameData,
     },
+    #[packet(id = 0x36)]
+    PlayerInfo(PlayerInfo),
+    #[packet(id = 0x38)]
     PlayerPositionAndLook {
         x: f64,
         y: f64,
@@ -143,27 +167,79 @@ pub enum OutgoingPlayPacket {
         yaw: f32,
         pitch: f32,
         flags: u8,
+        #[var_int]
         teleport_id: i32,
         dismount: bool,
     },
+    #[packet(id = 0x3A)]
+    DestroyEntities(EntityIds),
+    #[packet(id = 0x3E)]
+    EntityHeadLook {
+        #[var_int]
+        entity_id: i32,
+        head_yaw: u8,
+    },
+    #[packet(id = 0x49)]
     UpdateViewPosition {
+        #[var_int]
         chunk_x: i32,
+        #[var_int]
         chunk_z: i32,
     },
+    #[packet(id = 0x58)]
+    SetSubtitleText {
+        #[json]
+        text: Box<ChatComponent>,
+    },
+    #[packet(id = 0x5A)]
+    SetTitleText {
+        #[json]
+        text: Box<ChatComponent>,
+    },
+    /// In ticks
+    #[packet(id = 0x5B)]
+    SetTitleTimes {
+        fade_in: i32,
+        stay: i32,
+        fade_out: i32,
+    },
+    #[packet(id = 0x5F)]
+    PlayerListHeaderAndFooter {
+        #[json]
+        header: Box<ChatComponent>,
+        #[json]
+        footer: Box<ChatComponent>,
+    },
+    #[packet(id = 0x62)]
+    EntityTeleport {
+        #[var_int]
+        entity_id: i32,
+        x: f64,
+        y: f64,
+        z: f64,
+        yaw: u8,
+        pitch: u8,
+        on_ground: bool,
+    },
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PacketField)]
 pub struct JoinGameData {
     is_hardcore: bool,
     gamemode: Gamemode,
     previous_gamemode: Gamemode,
     dimension_names: Vec<String>,
+    #[nbt]
     dimension_codec: DimensionCodec,
+    #[nbt]
     dimension: DimensionType,
     dimension_name: String,
     hashed_seed: i64,
+    #[var_int]
     max_players: i32,
+    #[var_int]
     view_distance: i32,
+    #[var_int]
     simulation_distance: i32,
     reduced_debug_info: bool,
     enable_respawn_screen: bool,
@@ -171,171 +247,129 @@ pub struct JoinGameData {
     is_flat: bool,
 }
 
-impl OutgoingPlayPacket {
-    pub fn write(&self) -> Result<PacketPayload> {
-        let mut payload = PacketPayload::new(self.packet_id());
-        match self {
-            OutgoingPlayPacket::BlockEntityData(block_entity) => {
-                payload.write_position(block_entity.x, block_entity.y, block_entity.z)?;
-                payload.write_var_int(block_ids::get_block_entity_id(&block_entity.id).expect("invalid block entity ID"))?;
-                payload.write_nbt(&block_entity.data)?;
-            }
-            OutgoingPlayPacket::CustomPayload(payload_data) => {
-                payload.write_string(payload_data.channel_id(), 32767)?;
-                payload_data.write(&mut payload)?;
-            }
-            OutgoingPlayPacket::KeepAlive(v) => {
-                payload.write_ulong(*v)?;
-            }
-            OutgoingPlayPacket::ChunkData { chunk } => {
-                let mut heightmap = vec![0x0100804020100804; 36];
-                heightmap.push(0x0000000020100804);
-                payload.write_int(chunk.x)?;
-                payload.write_int(chunk.z)?;
-                payload.write_nbt(&Heightmaps {
-                    motion_blocking: heightmap,
-                })?;
-                let mut data = Vec::<u8>::new();
-                chunk.write(&mut data)?;
-                payload.write_var_int(data.len() as i32)?;
-                payload.write_bytes(&data)?;
-                payload.write_var_int(0)?; // block entities
-                // TODO: true or false? does it matter?
-                payload.write_bool(true)?; // trust edges
-                payload.write_ulong_array(&[])?; // sky light mask
-                payload.write_ulong_array(&[])?; // block light mask
-                payload.write_ulong_array(&[])?; // empty sky light mask
-                payload.write_ulong_array(&[])?; // empty block light mask
-                payload.write_var_int(0)?; // sky light array count
-                payload.write_var_int(0)?; // block light array count
-            }
-            OutgoingPlayPacket::UpdateLight { chunk_x, chunk_z } => {
-                payload.write_var_int(*chunk_x)?;
-                payload.write_var_int(*chunk_z)?;
-                payload.write_bool(true)?;
-                payload.write_ulong_array(&[])?; // sky light mask
-                payload.write_ulong_array(&[])?; // block light mask
-                payload.write_ulong_array(&[])?; // empty sky light mask
-                payload.write_ulong_array(&[])?; // empty block light mask
-                payload.write_var_int(0)?; // sky light array count
-                payload.write_var_int(0)?; // block light array count
-            }
-            OutgoingPlayPacket::JoinGame {
-                entity_id,
-                data:
-                    JoinGameData {
-                        is_hardcore,
-                        gamemode,
-                        previous_gamemode,
-                        dimension_names,
-                        dimension_codec,
-                        dimension,
-                        dimension_name,
-                        hashed_seed,
-                        max_players,
-                        view_distance,
-                        simulation_distance,
-                        reduced_debug_info,
-                        enable_respawn_screen,
-                        is_debug,
-                        is_flat,
-                    },
-            } => {
-                payload.write_int(*entity_id)?;
-                payload.write_bool(*is_hardcore)?;
-                gamemode.write(&mut payload)?;
-                previous_gamemode.write(&mut payload)?;
-                payload.write_string_arr(dimension_names)?;
-                payload.write_nbt(dimension_codec)?;
-                payload.write_nbt(dimension)?;
-                payload.write_string(&dimension_name, 32767)?;
-                payload.write_long(*hashed_seed)?;
-                payload.write_var_int(*max_players)?;
-                payload.write_var_int(*view_distance)?;
-                payload.write_var_int(*simulation_distance)?;
-                payload.write_bool(*reduced_debug_info)?;
-                payload.write_bool(*enable_respawn_screen)?;
-                payload.write_bool(*is_debug)?;
-                payload.write_bool(*is_flat)?;
-            }
-            OutgoingPlayPacket::PlayerPositionAndLook {
-                x,
-                y,
-                z,
-                yaw,
-                pitch,
-                flags,
-                teleport_id,
-                dismount,
-            } => {
-                payload.write_double(*x)?;
-                payload.write_double(*y)?;
-                payload.write_double(*z)?;
-                payload.write_float(*yaw)?;
-                payload.write_float(*pitch)?;
-                payload.write_ubyte(*flags)?;
-                payload.write_var_int(*teleport_id)?;
-                payload.write_bool(*dismount)?;
-            }
-            OutgoingPlayPacket::UpdateViewPosition { chunk_x, chunk_z } => {
-                payload.write_var_int(*chunk_x)?;
-                payload.write_var_int(*chunk_z)?;
-            }
-        }
-        Ok(payload)
+impl JoinGameData {
+    pub fn gamemode(&self) -> Gamemode {
+        self.gamemode
     }
 
-    fn packet_id(&self) -> i32 {
-        match self {
-            OutgoingPlayPacket::BlockEntityData(_) => 0x0a,
-            OutgoingPlayPacket::CustomPayload(_) => 0x18,
-            OutgoingPlayPacket::KeepAlive(_) => 0x21,
-            OutgoingPlayPacket::ChunkData { .. } => 0x22,
-            OutgoingPlayPacket::UpdateLight { .. } => 0x25,
-            OutgoingPlayPacket::JoinGame { .. } => 0x26,
-            OutgoingPlayPacket::PlayerPositionAndLook { .. } => 0x38,
-            OutgoingPlayPacket::UpdateViewPosition { .. } => 0x49,
+    pub fn min_y(&self) -> i32 {
+        self.dimension.min_y()
+    }
+}
+
+/// Where a chat message is shown on the client
+#[derive(Clone, Copy, Debug)]
+pub enum ChatPosition {
+    Chat,
+    System,
+    ActionBar,
+}
+
+impl PacketField for ChatPosition {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        match rdr.read_byte()? {
+            0 => Ok(Self::Chat),
+            1 => Ok(Self::System),
+            2 => Ok(Self::ActionBar),
+            v => Err(ProtocolError::InvalidEnumValue(v as i32)),
         }
     }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_byte(match self {
+            Self::Chat => 0,
+            Self::System => 1,
+            Self::ActionBar => 2,
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum PlayCustomPayload {
     MinecraftBrand { brand: String },
+    /// Asks a BungeeCord or Velocity proxy to move the player to another server
+    BungeeCordConnect { server: String },
+    /// Any channel we don't understand, kept around as raw data
+    Unknown { channel: String, data: Vec<u8> },
 }
 
-impl PlayCustomPayload {
-    fn write<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+impl PacketField for PlayCustomPayload {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let channel = rdr.read_string(32767)?;
+        match &*channel {
+            "minecraft:brand" => Ok(Self::MinecraftBrand {
+                brand: rdr.read_string(32767)?,
+            }),
+            BUNGEECORD_CHANNEL => {
+                let data = rdr.read_remaining()?;
+                let mut message = Cursor::new(&data[..]);
+                match (read_java_utf(&mut message), read_java_utf(&mut message)) {
+                    (Ok(subchannel), Ok(server)) if subchannel == "Connect" => Ok(Self::BungeeCordConnect { server }),
+                    _ => Ok(Self::Unknown { channel, data }),
+                }
+            }
+            c => {
+                info!("unknown channel: {}", c);
+                Ok(Self::Unknown {
+                    data: rdr.read_remaining()?,
+                    channel,
+                })
+            }
+        }
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
         match self {
             PlayCustomPayload::MinecraftBrand { brand } => {
+                wr.write_string("minecraft:brand", 32767)?;
                 wr.write_string(brand, 32767)?;
             }
+            PlayCustomPayload::BungeeCordConnect { server } => {
+                wr.write_string(BUNGEECORD_CHANNEL, 32767)?;
+                write_java_utf(wr, "Connect")?;
+                write_java_utf(wr, server)?;
+            }
+            PlayCustomPayload::Unknown { channel, data } => {
+                wr.write_string(channel, 32767)?;
+                wr.write_bytes(data)?;
+            }
         }
         Ok(())
     }
+}
 
-    fn read<R: PacketReader>(rdr: &mut R) -> Result<Option<Self>> {
-        let channel_id = rdr.read_string(32767)?;
-        match &*channel_id {
-            "minecraft:brand" => Ok(Some(Self::read_brand(rdr)?)),
-            c => {
-                info!("unknown channel: {}", c);
-                Ok(None)
-            }
+/// Sends the player to the upstream server through the proxy, or disconnects them so that they can reconnect
+/// if we don't know how. Returns whether the connection is still open.
+async fn transfer<W: Sink<PacketPayload, Error = ProtocolError> + Unpin>(wr: &mut W, store: &ServerStore) -> Result<bool> {
+    match store.get_config().upstream.as_ref().and_then(|u| u.proxy_server.as_ref()) {
+        Some(server) => {
+            let payload = PlayCustomPayload::BungeeCordConnect { server: server.clone() };
+            send_play_packet(wr, OutgoingPlayPacket::CustomPayload(payload)).await?;
+            Ok(true)
+        }
+        None => {
+            store.get_metrics().record_disconnect("upstream_up");
+            let reason = ChatComponent::from_text("The server is back up, reconnect to join!", ComponentStyle::v1_16());
+            send_play_packet(wr, OutgoingPlayPacket::Disconnect { reason }).await?;
+            Ok(false)
         }
     }
+}
 
-    fn read_brand<R: PacketReader>(rdr: &mut R) -> Result<Self> {
-        Ok(Self::MinecraftBrand {
-            brand: rdr.read_string(32767)?,
-        })
-    }
+const BUNGEECORD_CHANNEL: &str = "bungeecord:main";
 
-    fn channel_id(&self) -> &'static str {
-        match self {
-            PlayCustomPayload::MinecraftBrand { .. } => "minecraft:brand",
-        }
-    }
+/// How often other players are told about changes to our latency, which is about as often as vanilla does
+const LATENCY_BROADCAST_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Strings in BungeeCord messages are written with Java's `DataOutput.writeUTF`, which uses a u16 length
+fn read_java_utf<R: PacketReader>(rdr: &mut R) -> Result<String> {
+    let len = rdr.read_ushort()?;
+    Ok(String::from_utf8(rdr.read_bytes(len as usize)?)?)
+}
+
+fn write_java_utf<W: PacketWriter>(wr: &mut W, s: &str) -> Result<()> {
+    wr.write_ushort(s.len() as u16)?;
+    wr.write_bytes(s.as_bytes())
 }
 
 async fn send_play_packet<W: Sink<PacketPayload, Error = ProtocolError> + Unpin>(
@@ -354,15 +388,18 @@ pub async fn handle<
     rdr: &mut R,
     wr: &mut W,
     uuid: Uuid,
+    profile: Arc<Profile>,
+    session: &SessionGuard,
+    mut messages: mpsc::Receiver<SessionMessage>,
     store: ServerStore,
 ) -> Result<()> {
-    let entity_id = store.get_player_id(uuid).await;
+    let entity_id = session.entity_id();
 
     send_play_packet(
         wr,
         OutgoingPlayPacket::JoinGame {
             entity_id,
-            data: store.get_config().join_game_data.clone(),
+            data: profile.join_game_data.clone(),
         },
     )
     .await?;
@@ -370,60 +407,199 @@ pub async fn handle<
     send_play_packet(
         wr,
         OutgoingPlayPacket::CustomPayload(PlayCustomPayload::MinecraftBrand {
-            brand: store.get_config().server_brand.clone(),
+            brand: profile.server_brand.clone(),
         }),
     )
     .await?;
 
-    let config = store.get_config();
-
     tokio::time::sleep(Duration::from_millis(2000)).await;
 
-    let position_and_look = OutgoingPlayPacket::PlayerPositionAndLook {
-        x: config.spawn_point.0,
-        y: config.spawn_point.1,
-        z: config.spawn_point.2,
+    let spawn = Position {
+        x: profile.spawn_point.0,
+        y: profile.spawn_point.1,
+        z: profile.spawn_point.2,
         yaw: 0.0,
         pitch: 0.0,
-        flags: 0,
-        teleport_id: 0,
-        dismount: false,
+        on_ground: false,
     };
+    // Players pick up where they left off, as long as it's in the same map and somewhere they're still allowed to be
+    let mut position = store
+        .get_player_data()
+        .and_then(|player_data| player_data.record_join(uuid))
+        .filter(|saved| saved.map_file == profile.map_file)
+        .map(|saved| Position {
+            x: saved.x,
+            y: saved.y,
+            z: saved.z,
+            yaw: saved.yaw,
+            pitch: saved.pitch,
+            on_ground: false,
+        })
+        .filter(|saved| !movement::out_of_bounds(&profile, saved))
+        .unwrap_or(spawn);
 
-    send_play_packet(wr, position_and_look.clone()).await?;
+    let mut teleports = Teleports::default();
+    send_play_packet(wr, teleports.teleport((position.x, position.y, position.z), position.yaw, position.pitch)).await?;
 
-    for chunk in store.get_chunks() {
+    let chunks_started = Instant::now();
+    for chunk in &profile.world.chunks {
         send_play_packet(wr, OutgoingPlayPacket::ChunkData {
             chunk: chunk.clone(),
         }).await?;
 
-        for block_entity in store.get_block_entities() {
+        for block_entity in &profile.world.block_entities {
             send_play_packet(wr, OutgoingPlayPacket::BlockEntityData(block_entity.clone())).await?;
         }
     }
 
+    store.get_metrics().record_chunk_send(chunks_started.elapsed());
+
     send_play_packet(wr, OutgoingPlayPacket::UpdateViewPosition {
         chunk_x: 0,
         chunk_z: 0,
     }).await?;
 
-    send_play_packet(wr, position_and_look.clone()).await?;
+    send_play_packet(wr, teleports.teleport((position.x, position.y, position.z), position.yaw, position.pitch)).await?;
+    // So that players who never move are still saved and shown where they are
+    session.set_position(position);
+
+    // This includes us, so that we get our own skin
+    let mut player_events = store.subscribe_player_events();
+    let mut player_moves = store.subscribe_player_moves();
+    let mut listed_players = ListedPlayers::new(uuid, &profile);
+    for info in listed_players.sync(&store.get_online_players()) {
+        send_play_packet(wr, OutgoingPlayPacket::PlayerInfo(info)).await?;
+    }
+
+    let (nodes, root_index) = commands::command_graph(&commands::enabled_commands(&store));
+    send_play_packet(wr, OutgoingPlayPacket::DeclareCommands { nodes, root_index }).await?;
+
+    let mut shared_world = match &store.get_config().shared_world {
+        Some(config) if !profile.hide_players => Some(SharedWorld::new(uuid, profile.id, config.max_visible_players)),
+        _ => None,
+    };
+    if let Some(shared_world) = &mut shared_world {
+        for packet in shared_world.spawn_existing(&store.get_online_players()) {
+            send_play_packet(wr, packet).await?;
+        }
+    }
+    // Players are never given a place in line without a queue, so there'd be nothing to show
+    let mut queue_display = profile.queue_display.clone().filter(|_| store.is_queue_enabled()).map(QueueDisplay::new);
+    let refresh_interval = queue_display.as_ref().map_or(1, QueueDisplay::refresh_interval);
+    let mut queue_display_interval = interval(Duration::from_secs(refresh_interval));
+    queue_display_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    // The first tick is immediate, so the header and footer are sent straight away
+    let mut tab_list = profile.tab_list.clone().map(TabList::new);
+    let refresh_interval = tab_list.as_ref().map_or(1, TabList::refresh_interval);
+    let mut tab_list_interval = interval(Duration::from_secs(refresh_interval));
+    tab_list_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let mut announcer = Announcer::new(&store.get_config().announcements);
+
+    let mut chat = ChatSession::default();
 
     let mut keep_alive_interval = interval(Duration::from_millis(1000));
     keep_alive_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut last_keep_alive = None;
+    let mut latency_interval = interval(LATENCY_BROADCAST_INTERVAL);
+    latency_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
     loop {
+        let next_announcement = announcer.next_due();
         tokio::select! {
             d = rdr.try_next() => {
                 match d {
                     Ok(Some(mut packet_data)) => {
                         let packet = IncomingPlayPacket::read(packet_data.packet_id, &mut packet_data)?;
                         if let Some(packet) = packet {
+                            let mut moved_to = None;
                             match &packet {
-                                IncomingPlayPacket::TeleportConfirm { .. }
-                                | IncomingPlayPacket::ClientSettings { .. }
+                                IncomingPlayPacket::KeepAlive(id) => {
+                                    if let Some((sent_id, sent_at)) = last_keep_alive {
+                                        if sent_id == *id as u64 {
+                                            session.set_latency(Instant::now().duration_since(sent_at).as_millis() as i32);
+                                        }
+                                    }
+                                }
+                                IncomingPlayPacket::PlayerPosition { x, y, z, on_ground } => {
+                                    moved_to = Some(Position { x: *x, y: *y, z: *z, on_ground: *on_ground, ..position });
+                                }
+                                IncomingPlayPacket::PlayerPositionAndRotation { x, y, z, yaw, pitch, on_ground } => {
+                                    moved_to = Some(Position { x: *x, y: *y, z: *z, yaw: *yaw, pitch: *pitch, on_ground: *on_ground });
+                                }
+                                IncomingPlayPacket::PlayerRotation { yaw, pitch, on_ground } => {
+                                    moved_to = Some(Position { yaw: *yaw, pitch: *pitch, on_ground: *on_ground, ..position });
+                                }
+                                // 1.18.2 doesn't have a separate packet for commands
+                                IncomingPlayPacket::ChatMessage { message } if message.starts_with('/') => {
+                                    match commands::execute(&store, &message[1..]) {
+                                        CommandAction::Reply(message) => {
+                                            send_play_packet(wr, OutgoingPlayPacket::ChatMessage {
+                                                message: *message,
+                                                position: ChatPosition::System,
+                                                sender: Uuid::nil(),
+                                            }).await?;
+                                        }
+                                        CommandAction::Respawn => {
+                                            position = spawn;
+                                            session.set_position(position);
+                                            send_play_packet(wr, teleports.teleport(profile.spawn_point, 0.0, 0.0)).await?;
+                                        }
+                                        CommandAction::Transfer => {
+                                            if !transfer(wr, &store).await? {
+                                                break;
+                                            }
+                                        }
+                                    }
+                                }
+                                IncomingPlayPacket::ChatMessage { message } => {
+                                    match chat.handle(&store, &profile, session.username(), message) {
+                                        ChatOutcome::Broadcast(message) => store.broadcast_chat(profile.id, uuid, message),
+                                        ChatOutcome::Reply(message) => {
+                                            send_play_packet(wr, OutgoingPlayPacket::ChatMessage {
+                                                message,
+                                                position: ChatPosition::System,
+                                                sender: Uuid::nil(),
+                                            }).await?;
+                                        }
+                                        ChatOutcome::Ignore => {}
+                                    }
+                                }
+                                IncomingPlayPacket::TeleportConfirm { teleport_id } => {
+                                    teleports.confirm(*teleport_id);
+                                }
+                                IncomingPlayPacket::ClientSettings { .. }
                                 | IncomingPlayPacket::CustomPayload(_) => info!("got packet: {:?}", packet),
-                                _ => {}
+                            }
+                            if let Some(to) = moved_to {
+                                match movement::check_move(&position, &to) {
+                                    MoveCheck::Invalid => {
+                                        warn!(%uuid, "invalid movement to {:?}, disconnecting", to);
+                                        store.get_metrics().record_disconnect("invalid_move");
+                                        let reason = ChatComponent::from_text("Invalid move player packet received", ComponentStyle::v1_16());
+                                        send_play_packet(wr, OutgoingPlayPacket::Disconnect { reason }).await?;
+                                        break;
+                                    }
+                                    // Like vanilla, otherwise valid movement is ignored until the last teleport is confirmed,
+                                    // since the client may still be sending positions from before it
+                                    _ if teleports.is_pending() => {}
+                                    MoveCheck::TooFast => {
+                                        debug!(%uuid, "player moved too quickly, sending them back");
+                                        let (x, y, z) = (position.x, position.y, position.z);
+                                        send_play_packet(wr, teleports.teleport((x, y, z), position.yaw, position.pitch)).await?;
+                                    }
+                                    MoveCheck::Valid if movement::out_of_bounds(&profile, &to) => {
+                                        debug!(%uuid, "player left the map, sending them back to spawn");
+                                        position = spawn;
+                                        session.set_position(position);
+                                        send_play_packet(wr, teleports.teleport(profile.spawn_point, 0.0, 0.0)).await?;
+                                    }
+                                    MoveCheck::Valid => {
+                                        position = to;
+                                        session.set_position(position);
+                                    }
+                                }
                             }
                         } else {
                             // only log these at a high level when compiled in debug mode
@@ -443,13 +619,120 @@ pub async fn handle<
                     Err(e) => {
                         return Err(e);
                     },
-                    _ => break,
+                    _ => {
+                        store.get_metrics().record_disconnect("client_closed");
+                        break;
+                    }
                 }
             }
             _ = keep_alive_interval.tick() => {
                 debug!("Sending keep alive packet");
                 let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("current time is before the unix epoch!?").as_secs();
                 send_play_packet(wr, OutgoingPlayPacket::KeepAlive(now)).await?;
+                last_keep_alive = Some((now, Instant::now()));
+            }
+            _ = latency_interval.tick() => session.broadcast_latency(),
+            _ = queue_display_interval.tick(), if queue_display.is_some() => {
+                if let Some(queue_display) = &mut queue_display {
+                    let packets = match session.queue_position() {
+                        Some((position, queue_size)) => queue_display.update(&store, &profile, position, queue_size),
+                        None => queue_display.clear(),
+                    };
+                    for packet in packets {
+                        send_play_packet(wr, packet).await?;
+                    }
+                }
+            }
+            _ = tab_list_interval.tick(), if tab_list.is_some() => {
+                if let Some(packet) = tab_list.as_mut().and_then(|t| t.update(&store, &profile)) {
+                    send_play_packet(wr, packet).await?;
+                }
+            }
+            // The fallback deadline is never waited on, since the branch is disabled without an announcement
+            _ = sleep_until(next_announcement.unwrap_or_else(tokio::time::Instant::now)), if next_announcement.is_some() => {
+                for packet in announcer.fire(&store, &profile, session.username()) {
+                    send_play_packet(wr, packet).await?;
+                }
+            }
+            // The store keeps hold of the sender for as long as the session is registered, so this never runs out
+            Some(message) = messages.recv() => {
+                match message {
+                    SessionMessage::Packet(packet) => send_play_packet(wr, *packet).await?,
+                    SessionMessage::Transfer => {
+                        if let Some(queue_display) = &mut queue_display {
+                            for packet in queue_display.clear() {
+                                send_play_packet(wr, packet).await?;
+                            }
+                        }
+                        if !transfer(wr, &store).await? {
+                            break;
+                        }
+                    }
+                    SessionMessage::Disconnect(reason) => {
+                        store.get_metrics().record_disconnect("kicked");
+                        send_play_packet(wr, OutgoingPlayPacket::Disconnect { reason: *reason }).await?;
+                        break;
+                    }
+                    SessionMessage::Shutdown => {
+                        let config = &store.get_config().shutdown;
+                        match &config.transfer_to {
+                            // The proxy closes the connection once the player has moved, which ends the session
+                            Some(server) => {
+                                let payload = PlayCustomPayload::BungeeCordConnect { server: server.clone() };
+                                send_play_packet(wr, OutgoingPlayPacket::CustomPayload(payload)).await?;
+                            }
+                            None => {
+                                store.get_metrics().record_disconnect("shutdown");
+                                send_play_packet(wr, OutgoingPlayPacket::Disconnect { reason: config.message.clone() }).await?;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            event = player_events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(%uuid, "missed {} player updates, resyncing", n);
+                        let players = store.get_online_players();
+                        for info in listed_players.sync(&players) {
+                            send_play_packet(wr, OutgoingPlayPacket::PlayerInfo(info)).await?;
+                        }
+                        if let Some(shared_world) = &mut shared_world {
+                            for packet in shared_world.resync(&players) {
+                                send_play_packet(wr, packet).await?;
+                            }
+                        }
+                        continue;
+                    }
+                    // The store keeps hold of the sender, so this can't happen
+                    Err(RecvError::Closed) => continue,
+                };
+                if let Some(info) = listed_players.handle_event(&event) {
+                    send_play_packet(wr, OutgoingPlayPacket::PlayerInfo(info)).await?;
+                }
+                if let Some(shared_world) = &mut shared_world {
+                    for packet in shared_world.handle_event(&event) {
+                        send_play_packet(wr, packet).await?;
+                    }
+                }
+            }
+            moved = player_moves.recv(), if shared_world.is_some() => {
+                let moved = match moved {
+                    Ok(moved) => moved,
+                    // Whoever was missed will be caught up by their next move
+                    Err(RecvError::Lagged(n)) => {
+                        debug!(%uuid, "missed {} player moves", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => continue,
+                };
+                if let Some(shared_world) = &mut shared_world {
+                    for packet in shared_world.handle_move(&moved) {
+                        send_play_packet(wr, packet).await?;
+                    }
+                }
             }
         }
     }
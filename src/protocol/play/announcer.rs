@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{announcements::AnnouncementConfig, placeholders::Placeholders, profile::Profile, store::ServerStore};
+
+use super::{ChatPosition, OutgoingPlayPacket};
+
+/// Keeps track of when each configured announcement is next due for a player
+#[derive(Debug)]
+pub struct Announcer {
+    /// Each announcement and when it's next due, if it still is
+    entries: Vec<(AnnouncementConfig, Option<Instant>)>,
+}
+
+impl Announcer {
+    pub fn new(announcements: &[AnnouncementConfig]) -> Self {
+        let now = Instant::now();
+        Self {
+            entries: announcements
+                .iter()
+                .map(|a| (a.clone(), Some(now + Duration::from_secs(a.delay))))
+                .collect(),
+        }
+    }
+
+    /// When the next announcement should be sent, if there are any left
+    pub fn next_due(&self) -> Option<Instant> {
+        self.entries.iter().filter_map(|(_, due)| *due).min()
+    }
+
+    /// Builds the packets for every announcement that's due, and schedules their repeats
+    pub fn fire(&mut self, store: &ServerStore, profile: &Profile, username: &str) -> Vec<OutgoingPlayPacket> {
+        let now = Instant::now();
+        let placeholders = Placeholders::new(store, profile).with("username", username);
+        let mut packets = Vec::new();
+
+        for (announcement, due) in &mut self.entries {
+            match due {
+                Some(at) if *at <= now => {}
+                _ => continue,
+            }
+            // Repeats are scheduled from now rather than from when it was due, so a slow connection doesn't cause a burst
+            *due = announcement.repeat.map(|repeat| now + Duration::from_secs(repeat.max(1)));
+
+            if let Some(message) = &announcement.chat {
+                packets.push(OutgoingPlayPacket::ChatMessage {
+                    message: placeholders.apply(message),
+                    position: ChatPosition::System,
+                    sender: Uuid::nil(),
+                });
+            }
+            if let Some(title) = &announcement.title {
+                packets.extend(title.packets(|text| placeholders.apply(text)));
+            }
+        }
+
+        packets
+    }
+}
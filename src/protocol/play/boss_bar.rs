@@ -0,0 +1,128 @@
+use mc_chat::ChatComponent;
+use serde::Deserialize;
+
+use crate::{
+    io::{PacketField, PacketReader, PacketWriter},
+    util::{ProtocolError, Result},
+};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BossBarColor {
+    Pink,
+    Blue,
+    Red,
+    Green,
+    Yellow,
+    #[default]
+    Purple,
+    White,
+}
+
+/// How many segments the bar is split into
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BossBarDivision {
+    #[default]
+    None,
+    Notches6,
+    Notches10,
+    Notches12,
+    Notches20,
+}
+
+impl BossBarColor {
+    fn id(self) -> i32 {
+        self as i32
+    }
+
+    fn from_id(id: i32) -> Result<Self> {
+        [Self::Pink, Self::Blue, Self::Red, Self::Green, Self::Yellow, Self::Purple, Self::White]
+            .get(id as usize)
+            .copied()
+            .ok_or(ProtocolError::InvalidEnumValue(id))
+    }
+}
+
+impl BossBarDivision {
+    fn id(self) -> i32 {
+        self as i32
+    }
+
+    fn from_id(id: i32) -> Result<Self> {
+        [Self::None, Self::Notches6, Self::Notches10, Self::Notches12, Self::Notches20]
+            .get(id as usize)
+            .copied()
+            .ok_or(ProtocolError::InvalidEnumValue(id))
+    }
+}
+
+/// What a Boss Bar packet does to the bar with its uuid
+#[derive(Clone, Debug)]
+pub enum BossBarAction {
+    Add {
+        title: ChatComponent,
+        /// From 0 to 1
+        health: f32,
+        color: BossBarColor,
+        division: BossBarDivision,
+        flags: u8,
+    },
+    Remove,
+    UpdateHealth(f32),
+    UpdateTitle(ChatComponent),
+    UpdateStyle {
+        color: BossBarColor,
+        division: BossBarDivision,
+    },
+}
+
+impl PacketField for BossBarAction {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        match rdr.read_var_int()? {
+            0 => Ok(Self::Add {
+                title: rdr.read_json()?,
+                health: rdr.read_float()?,
+                color: BossBarColor::from_id(rdr.read_var_int()?)?,
+                division: BossBarDivision::from_id(rdr.read_var_int()?)?,
+                flags: rdr.read_ubyte()?,
+            }),
+            1 => Ok(Self::Remove),
+            2 => Ok(Self::UpdateHealth(rdr.read_float()?)),
+            3 => Ok(Self::UpdateTitle(rdr.read_json()?)),
+            4 => Ok(Self::UpdateStyle {
+                color: BossBarColor::from_id(rdr.read_var_int()?)?,
+                division: BossBarDivision::from_id(rdr.read_var_int()?)?,
+            }),
+            v => Err(ProtocolError::InvalidEnumValue(v)),
+        }
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        match self {
+            Self::Add { title, health, color, division, flags } => {
+                wr.write_var_int(0)?;
+                wr.write_json(title)?;
+                wr.write_float(*health)?;
+                wr.write_var_int(color.id())?;
+                wr.write_var_int(division.id())?;
+                wr.write_ubyte(*flags)?;
+            }
+            Self::Remove => wr.write_var_int(1)?,
+            Self::UpdateHealth(health) => {
+                wr.write_var_int(2)?;
+                wr.write_float(*health)?;
+            }
+            Self::UpdateTitle(title) => {
+                wr.write_var_int(3)?;
+                wr.write_json(title)?;
+            }
+            Self::UpdateStyle { color, division } => {
+                wr.write_var_int(4)?;
+                wr.write_var_int(color.id())?;
+                wr.write_var_int(division.id())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{
+    io::{PacketField, PacketReader, PacketWriter},
+    util::{ProtocolError, Result},
+};
+
+/// A node in the Brigadier command graph sent in Declare Commands.
+/// Only literals are supported, as none of our commands take arguments.
+#[derive(Clone, Debug)]
+pub struct CommandNode {
+    pub kind: CommandNodeKind,
+    /// Whether the command is complete at this node, rather than needing more after it
+    pub executable: bool,
+    /// Indices of the child nodes in the packet's node list
+    pub children: Vec<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub enum CommandNodeKind {
+    Root,
+    Literal(String),
+}
+
+const NODE_TYPE_MASK: i8 = 0x03;
+const EXECUTABLE_FLAG: i8 = 0x04;
+
+impl PacketField for CommandNode {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let flags = rdr.read_byte()?;
+        let count = rdr.read_var_int()?;
+        let children = (0..count).map(|_| rdr.read_var_int()).collect::<Result<_>>()?;
+        let kind = match flags & NODE_TYPE_MASK {
+            0 => CommandNodeKind::Root,
+            1 => CommandNodeKind::Literal(rdr.read_string(32767)?),
+            v => return Err(ProtocolError::InvalidEnumValue(v as i32)),
+        };
+        Ok(Self {
+            kind,
+            executable: flags & EXECUTABLE_FLAG != 0,
+            children,
+        })
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        let node_type = match self.kind {
+            CommandNodeKind::Root => 0,
+            CommandNodeKind::Literal(_) => 1,
+        };
+        wr.write_byte(node_type | if self.executable { EXECUTABLE_FLAG } else { 0 })?;
+        wr.write_var_int(self.children.len() as i32)?;
+        for child in &self.children {
+            wr.write_var_int(*child)?;
+        }
+        if let CommandNodeKind::Literal(name) = &self.kind {
+            wr.write_string(name, 32767)?;
+        }
+        Ok(())
+    }
+}
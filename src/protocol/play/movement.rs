@@ -0,0 +1,156 @@
+use crate::{config::AreaConfig, profile::Profile, store::Position};
+
+use super::OutgoingPlayPacket;
+
+/// Whether a player has fallen into the void or wandered outside the configured area
+pub fn out_of_bounds(profile: &Profile, position: &Position) -> bool {
+    let bounds = &profile.bounds;
+    let min_y = bounds
+        .min_y
+        .unwrap_or_else(|| profile.join_game_data.min_y() as f64 - bounds.void_margin);
+    if position.y < min_y {
+        return true;
+    }
+
+    match bounds.area {
+        Some(AreaConfig { from: (x1, y1, z1), to: (x2, y2, z2) }) => {
+            let inside = |value: f64, a: f64, b: f64| value >= a.min(b) && value <= a.max(b);
+            !(inside(position.x, x1, x2) && inside(position.y, y1, y2) && inside(position.z, z1, z2))
+        }
+        None => false,
+    }
+}
+
+/// The furthest a player can move in one packet before being sent back, in blocks.
+/// Vanilla uses roughly the same limit, which is well above how far anyone can move in a tick without an elytra.
+const MAX_MOVE_DISTANCE: f64 = 10.0;
+
+/// Vanilla kicks players for coordinates past these, since nothing can legitimately be out there
+const MAX_HORIZONTAL_COORDINATE: f64 = 3.0e7;
+const MAX_VERTICAL_COORDINATE: f64 = 2.0e7;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MoveCheck {
+    Valid,
+    /// Moved further than anyone could have, so they should be sent back to where they were
+    TooFast,
+    /// NaN, infinite or absurdly large values, which only a broken or malicious client would send
+    Invalid,
+}
+
+/// Sanity checks a move from one position to another
+pub fn check_move(from: &Position, to: &Position) -> MoveCheck {
+    let finite = [to.x, to.y, to.z, to.yaw as f64, to.pitch as f64].iter().all(|v| v.is_finite());
+    if !finite
+        || to.x.abs() > MAX_HORIZONTAL_COORDINATE
+        || to.z.abs() > MAX_HORIZONTAL_COORDINATE
+        || to.y.abs() > MAX_VERTICAL_COORDINATE
+    {
+        return MoveCheck::Invalid;
+    }
+
+    let (dx, dy, dz) = (to.x - from.x, to.y - from.y, to.z - from.z);
+    if dx * dx + dy * dy + dz * dz > MAX_MOVE_DISTANCE * MAX_MOVE_DISTANCE {
+        MoveCheck::TooFast
+    } else {
+        MoveCheck::Valid
+    }
+}
+
+/// Hands out teleport IDs and keeps track of the latest teleport the client hasn't confirmed yet.
+/// Like vanilla, only the latest teleport counts, so confirming an older one doesn't clear it.
+#[derive(Debug, Default)]
+pub struct Teleports {
+    next_id: i32,
+    pending: Option<i32>,
+}
+
+impl Teleports {
+    /// Builds the packet to move the player, which they'll need to confirm
+    pub fn teleport(&mut self, (x, y, z): (f64, f64, f64), yaw: f32, pitch: f32) -> OutgoingPlayPacket {
+        let teleport_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending = Some(teleport_id);
+        OutgoingPlayPacket::PlayerPositionAndLook {
+            x,
+            y,
+            z,
+            yaw,
+            pitch,
+            flags: 0,
+            teleport_id,
+            dismount: false,
+        }
+    }
+
+    /// Handles a Teleport Confirm from the client, returning whether it was for the pending teleport
+    pub fn confirm(&mut self, teleport_id: i32) -> bool {
+        if self.pending == Some(teleport_id) {
+            self.pending = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_move, MoveCheck, Teleports};
+    use crate::store::Position;
+
+    fn at(x: f64, y: f64, z: f64) -> Position {
+        Position {
+            x,
+            y,
+            z,
+            yaw: 0.0,
+            pitch: 0.0,
+            on_ground: true,
+        }
+    }
+
+    #[test]
+    fn allows_normal_moves() {
+        let from = at(0.0, 64.0, 0.0);
+        assert_eq!(check_move(&from, &at(0.3, 64.0, -0.2)), MoveCheck::Valid);
+        assert_eq!(check_move(&from, &at(0.0, 54.0, 0.0)), MoveCheck::Valid);
+    }
+
+    #[test]
+    fn rejects_moves_that_are_too_fast() {
+        let from = at(0.0, 64.0, 0.0);
+        assert_eq!(check_move(&from, &at(10.1, 64.0, 0.0)), MoveCheck::TooFast);
+        assert_eq!(check_move(&from, &at(6.0, 70.0, 6.0)), MoveCheck::TooFast);
+    }
+
+    #[test]
+    fn rejects_invalid_coordinates() {
+        let from = at(0.0, 64.0, 0.0);
+        assert_eq!(check_move(&from, &at(f64::NAN, 64.0, 0.0)), MoveCheck::Invalid);
+        assert_eq!(check_move(&from, &at(0.0, f64::INFINITY, 0.0)), MoveCheck::Invalid);
+        assert_eq!(check_move(&from, &at(0.0, 64.0, 3.1e7)), MoveCheck::Invalid);
+        assert_eq!(check_move(&from, &at(0.0, -2.1e7, 0.0)), MoveCheck::Invalid);
+
+        let to = Position {
+            yaw: f32::NAN,
+            ..at(0.0, 64.0, 0.0)
+        };
+        assert_eq!(check_move(&from, &to), MoveCheck::Invalid);
+    }
+
+    #[test]
+    fn only_the_latest_teleport_counts() {
+        let mut teleports = Teleports::default();
+        teleports.teleport((0.0, 64.0, 0.0), 0.0, 0.0);
+        teleports.teleport((0.0, 70.0, 0.0), 0.0, 0.0);
+        assert!(!teleports.confirm(0));
+        assert!(teleports.is_pending());
+        assert!(teleports.confirm(1));
+        assert!(!teleports.is_pending());
+    }
+}
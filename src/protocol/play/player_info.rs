@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use mc_chat::ChatComponent;
+use uuid::Uuid;
+
+use crate::{
+    constants::Gamemode,
+    io::{PacketField, PacketReader, PacketWriter},
+    profile::Profile,
+    protocol::forwarding::ProfileProperty,
+    store::{OnlinePlayer, PlayerEvent},
+    util::{ProtocolError, Result},
+};
+
+/// The body of a Player Info packet, which keeps the client's tab list up to date.
+/// Each action applies to every player listed in it.
+#[derive(Clone, Debug)]
+pub enum PlayerInfo {
+    AddPlayers(Vec<PlayerInfoEntry>),
+    UpdateLatency(Vec<(Uuid, i32)>),
+    RemovePlayers(Vec<Uuid>),
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerInfoEntry {
+    pub uuid: Uuid,
+    pub username: String,
+    /// Carries the player's skin, if we were given one by the proxy
+    pub properties: Vec<ProfileProperty>,
+    pub gamemode: Gamemode,
+    /// Round trip time in milliseconds, shown as the connection bars in the tab list
+    pub latency: i32,
+    /// Replaces the username in the tab list, if set
+    pub display_name: Option<ChatComponent>,
+}
+
+impl From<&OnlinePlayer> for PlayerInfoEntry {
+    fn from(player: &OnlinePlayer) -> Self {
+        Self {
+            uuid: player.uuid,
+            username: player.username.clone(),
+            properties: player.properties.clone(),
+            gamemode: player.gamemode,
+            latency: player.latency,
+            display_name: player.display_name.clone(),
+        }
+    }
+}
+
+/// Keeps track of who's in one player's tab list, which only shows players in the same profile,
+/// or nobody else if the profile hides players
+#[derive(Debug)]
+pub struct ListedPlayers {
+    uuid: Uuid,
+    profile_id: usize,
+    hide_players: bool,
+    listed: HashSet<Uuid>,
+}
+
+impl ListedPlayers {
+    pub fn new(uuid: Uuid, profile: &Profile) -> Self {
+        Self {
+            uuid,
+            profile_id: profile.id,
+            hide_players: profile.hide_players,
+            listed: HashSet::new(),
+        }
+    }
+
+    /// We're always listed, so that we get our own skin
+    fn should_list(&self, uuid: Uuid, profile_id: usize) -> bool {
+        uuid == self.uuid || (!self.hide_players && profile_id == self.profile_id)
+    }
+
+    /// Replaces whoever is listed with the given players, as far as they should be listed
+    pub fn sync(&mut self, players: &[OnlinePlayer]) -> Vec<PlayerInfo> {
+        let mut infos = Vec::new();
+        if !self.listed.is_empty() {
+            infos.push(PlayerInfo::RemovePlayers(self.listed.drain().collect()));
+        }
+        let entries: Vec<_> = players
+            .iter()
+            .filter(|p| self.should_list(p.uuid, p.profile_id))
+            .map(PlayerInfoEntry::from)
+            .collect();
+        self.listed.extend(entries.iter().map(|e| e.uuid));
+        infos.push(PlayerInfo::AddPlayers(entries));
+        infos
+    }
+
+    pub fn handle_event(&mut self, event: &PlayerEvent) -> Option<PlayerInfo> {
+        match event {
+            PlayerEvent::Joined(player) if self.should_list(player.uuid, player.profile_id) => {
+                self.listed
+                    .insert(player.uuid)
+                    .then(|| PlayerInfo::AddPlayers(vec![PlayerInfoEntry::from(&**player)]))
+            }
+            PlayerEvent::Left { uuid, .. } if *uuid != self.uuid => {
+                self.listed.remove(uuid).then(|| PlayerInfo::RemovePlayers(vec![*uuid]))
+            }
+            PlayerEvent::LatencyChanged { uuid, profile_id, latency } if self.should_list(*uuid, *profile_id) => {
+                Some(PlayerInfo::UpdateLatency(vec![(*uuid, *latency)]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PacketField for PlayerInfo {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let action = rdr.read_var_int()?;
+        let count = rdr.read_var_int()?;
+        match action {
+            0 => (0..count)
+                .map(|_| {
+                    Ok(PlayerInfoEntry {
+                        uuid: rdr.read_uuid()?,
+                        username: rdr.read_string(16)?,
+                        properties: Vec::read_field(rdr)?,
+                        gamemode: Gamemode::from_id(rdr.read_var_int()?)?,
+                        latency: rdr.read_var_int()?,
+                        display_name: if rdr.read_bool()? { Some(rdr.read_json()?) } else { None },
+                    })
+                })
+                .collect::<Result<_>>()
+                .map(PlayerInfo::AddPlayers),
+            2 => (0..count)
+                .map(|_| Ok((rdr.read_uuid()?, rdr.read_var_int()?)))
+                .collect::<Result<_>>()
+                .map(PlayerInfo::UpdateLatency),
+            4 => (0..count)
+                .map(|_| rdr.read_uuid())
+                .collect::<Result<_>>()
+                .map(PlayerInfo::RemovePlayers),
+            v => Err(ProtocolError::InvalidEnumValue(v)),
+        }
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        match self {
+            PlayerInfo::AddPlayers(entries) => {
+                wr.write_var_int(0)?;
+                wr.write_var_int(entries.len() as i32)?;
+                for entry in entries {
+                    wr.write_uuid(&entry.uuid)?;
+                    wr.write_string(&entry.username, 16)?;
+                    entry.properties.write_field(wr)?;
+                    wr.write_var_int(entry.gamemode.id() as i32)?;
+                    wr.write_var_int(entry.latency)?;
+                    wr.write_bool(entry.display_name.is_some())?;
+                    if let Some(display_name) = &entry.display_name {
+                        wr.write_json(display_name)?;
+                    }
+                }
+            }
+            PlayerInfo::UpdateLatency(latencies) => {
+                wr.write_var_int(2)?;
+                wr.write_var_int(latencies.len() as i32)?;
+                for (uuid, latency) in latencies {
+                    wr.write_uuid(uuid)?;
+                    wr.write_var_int(*latency)?;
+                }
+            }
+            PlayerInfo::RemovePlayers(uuids) => {
+                wr.write_var_int(4)?;
+                wr.write_var_int(uuids.len() as i32)?;
+                for uuid in uuids {
+                    wr.write_uuid(uuid)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use uuid::Uuid;
+
+use crate::{
+    placeholders::Placeholders,
+    profile::Profile,
+    queue::{BossBarProgress, QueueDisplayConfig},
+    store::ServerStore,
+};
+
+use super::{boss_bar::BossBarAction, ChatPosition, OutgoingPlayPacket};
+
+/// Each client only ever has the one boss bar, so it doesn't need to be unique
+const QUEUE_BOSS_BAR: Uuid = Uuid::from_u128(0x66616c6c_626c_6f63_6b00_717565756500);
+
+/// Keeps a player's boss bar and action bar showing their place in the queue
+#[derive(Debug)]
+pub struct QueueDisplay {
+    config: QueueDisplayConfig,
+    /// The title (as JSON, since components can't be compared) and health the boss bar is showing, once it's been added
+    boss_bar: Option<(String, f32)>,
+}
+
+impl QueueDisplay {
+    pub fn new(config: QueueDisplayConfig) -> Self {
+        Self {
+            config,
+            boss_bar: None,
+        }
+    }
+
+    pub fn refresh_interval(&self) -> u64 {
+        self.config.refresh_interval.max(1)
+    }
+
+    /// Builds the packets needed to take the boss bar away, once the player is no longer queued
+    pub fn clear(&mut self) -> Vec<OutgoingPlayPacket> {
+        match self.boss_bar.take() {
+            Some(_) => vec![OutgoingPlayPacket::BossBar {
+                uuid: QUEUE_BOSS_BAR,
+                action: BossBarAction::Remove,
+            }],
+            None => Vec::new(),
+        }
+    }
+
+    /// Builds the packets needed to show the given (1-based) queue position
+    pub fn update(&mut self, store: &ServerStore, profile: &Profile, position: usize, queue_size: usize) -> Vec<OutgoingPlayPacket> {
+        let placeholders = Placeholders::new(store, profile)
+            .with("position", position)
+            .with("queue_size", queue_size);
+        let mut packets = Vec::new();
+
+        if let Some(config) = &self.config.boss_bar {
+            let title = placeholders.apply(&config.title);
+            let title_json = serde_json::to_string(&title).unwrap_or_default();
+            let health = match config.progress {
+                BossBarProgress::Fixed(health) => health,
+                BossBarProgress::Queue if queue_size > 0 => 1.0 - (position - 1) as f32 / queue_size as f32,
+                BossBarProgress::Queue => 1.0,
+            }
+            .clamp(0.0, 1.0);
+
+            match &self.boss_bar {
+                None => packets.push(OutgoingPlayPacket::BossBar {
+                    uuid: QUEUE_BOSS_BAR,
+                    action: BossBarAction::Add {
+                        title,
+                        health,
+                        color: config.color,
+                        division: config.division,
+                        flags: 0,
+                    },
+                }),
+                Some((last_title, last_health)) => {
+                    if *last_title != title_json {
+                        packets.push(OutgoingPlayPacket::BossBar {
+                            uuid: QUEUE_BOSS_BAR,
+                            action: BossBarAction::UpdateTitle(title),
+                        });
+                    }
+                    if *last_health != health {
+                        packets.push(OutgoingPlayPacket::BossBar {
+                            uuid: QUEUE_BOSS_BAR,
+                            action: BossBarAction::UpdateHealth(health),
+                        });
+                    }
+                }
+            }
+            self.boss_bar = Some((title_json, health));
+        }
+
+        // The action bar fades out on its own, so it's sent every time
+        if let Some(action_bar) = &self.config.action_bar {
+            packets.push(OutgoingPlayPacket::ChatMessage {
+                message: placeholders.apply(action_bar),
+                position: ChatPosition::ActionBar,
+                sender: Uuid::nil(),
+            });
+        }
+
+        packets
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{
+    io::{PacketField, PacketReader, PacketWriter},
+    store::{OnlinePlayer, PlayerEvent, PlayerMoved, Position},
+    util::Result,
+};
+
+use super::OutgoingPlayPacket;
+
+/// The entity ids in a Destroy Entities packet
+#[derive(Clone, Debug)]
+pub struct EntityIds(pub Vec<i32>);
+
+impl PacketField for EntityIds {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let count = rdr.read_var_int()?;
+        (0..count).map(|_| rdr.read_var_int()).collect::<Result<_>>().map(Self)
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_var_int(self.0.len() as i32)?;
+        for id in &self.0 {
+            wr.write_var_int(*id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts degrees into the 256ths of a turn used by entity packets
+pub fn angle(degrees: f32) -> u8 {
+    (degrees.rem_euclid(360.0) / 360.0 * 256.0) as i32 as u8
+}
+
+/// Keeps track of which other players have been spawned in for one player
+#[derive(Debug)]
+pub struct SharedWorld {
+    uuid: Uuid,
+    profile_id: usize,
+    max_visible: usize,
+    /// Entity ids of the players we've spawned, keyed by uuid
+    visible: HashMap<Uuid, i32>,
+    /// Everyone in our profile who's online, as far as player events say. Moves come separately,
+    /// so this stops a late one from spawning somebody who has already left.
+    online: HashSet<Uuid>,
+}
+
+impl SharedWorld {
+    pub fn new(uuid: Uuid, profile_id: usize, max_visible: usize) -> Self {
+        Self {
+            uuid,
+            profile_id,
+            max_visible,
+            visible: HashMap::new(),
+            online: HashSet::new(),
+        }
+    }
+
+    /// Spawns in everyone who was already around when we joined
+    pub fn spawn_existing(&mut self, players: &[OnlinePlayer]) -> Vec<OutgoingPlayPacket> {
+        let profile_id = self.profile_id;
+        let players: Vec<_> = players.iter().filter(|p| p.profile_id == profile_id).collect();
+        self.online = players.iter().map(|p| p.uuid).collect();
+        players
+            .into_iter()
+            .filter_map(|p| Some((p, p.position?)))
+            .filter_map(|(p, position)| self.spawn(p.uuid, p.entity_id, position))
+            .collect()
+    }
+
+    /// Despawns everyone and spawns in whoever is around now, for when events have been missed
+    pub fn resync(&mut self, players: &[OnlinePlayer]) -> Vec<OutgoingPlayPacket> {
+        let mut packets = Vec::new();
+        if !self.visible.is_empty() {
+            let entity_ids = self.visible.drain().map(|(_, entity_id)| entity_id).collect();
+            packets.push(OutgoingPlayPacket::DestroyEntities(EntityIds(entity_ids)));
+        }
+        packets.extend(self.spawn_existing(players));
+        packets
+    }
+
+    pub fn handle_event(&mut self, event: &PlayerEvent) -> Vec<OutgoingPlayPacket> {
+        match event {
+            PlayerEvent::Joined(player) if player.profile_id == self.profile_id => {
+                self.online.insert(player.uuid);
+                Vec::new()
+            }
+            PlayerEvent::Left { uuid, .. } => {
+                self.online.remove(uuid);
+                match self.visible.remove(uuid) {
+                    Some(entity_id) => vec![OutgoingPlayPacket::DestroyEntities(EntityIds(vec![entity_id]))],
+                    None => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn handle_move(&mut self, moved: &PlayerMoved) -> Vec<OutgoingPlayPacket> {
+        let PlayerMoved { uuid, entity_id, profile_id, position } = moved;
+        if *profile_id != self.profile_id || !self.online.contains(uuid) {
+            return Vec::new();
+        }
+        if self.visible.contains_key(uuid) {
+            vec![
+                OutgoingPlayPacket::EntityTeleport {
+                    entity_id: *entity_id,
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                    yaw: angle(position.yaw),
+                    pitch: angle(position.pitch),
+                    on_ground: position.on_ground,
+                },
+                OutgoingPlayPacket::EntityHeadLook {
+                    entity_id: *entity_id,
+                    head_yaw: angle(position.yaw),
+                },
+            ]
+        } else {
+            // Anyone who didn't fit before gets a chance whenever they move, in case somebody left
+            self.spawn(*uuid, *entity_id, *position).into_iter().collect()
+        }
+    }
+
+    fn spawn(&mut self, uuid: Uuid, entity_id: i32, position: Position) -> Option<OutgoingPlayPacket> {
+        if uuid == self.uuid || self.visible.len() >= self.max_visible {
+            return None;
+        }
+        self.visible.insert(uuid, entity_id);
+        Some(OutgoingPlayPacket::SpawnPlayer {
+            entity_id,
+            uuid,
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            yaw: angle(position.yaw),
+            pitch: angle(position.pitch),
+        })
+    }
+}
@@ -0,0 +1,51 @@
+use mc_chat::{ChatComponent, ComponentStyle};
+
+use crate::{config::TabListConfig, placeholders::Placeholders, profile::Profile, store::ServerStore};
+
+use super::OutgoingPlayPacket;
+
+/// Keeps a player's tab list header and footer up to date
+#[derive(Debug)]
+pub struct TabList {
+    config: TabListConfig,
+    /// The header and footer last sent, as JSON since components can't be compared
+    last_sent: Option<(String, String)>,
+}
+
+impl TabList {
+    pub fn new(config: TabListConfig) -> Self {
+        Self {
+            config,
+            last_sent: None,
+        }
+    }
+
+    pub fn refresh_interval(&self) -> u64 {
+        self.config.refresh_interval.max(1)
+    }
+
+    /// Builds the packet to send, if anything has changed since last time
+    pub fn update(&mut self, store: &ServerStore, profile: &Profile) -> Option<OutgoingPlayPacket> {
+        let placeholders = Placeholders::new(store, profile);
+        // An empty component leaves that part of the tab list blank
+        let render = |component: &Option<ChatComponent>| match component {
+            Some(component) => placeholders.apply(component),
+            None => ChatComponent::from_text("", ComponentStyle::v1_16()),
+        };
+        let header = render(&self.config.header);
+        let footer = render(&self.config.footer);
+
+        let json = (
+            serde_json::to_string(&header).unwrap_or_default(),
+            serde_json::to_string(&footer).unwrap_or_default(),
+        );
+        if self.last_sent.as_ref() == Some(&json) {
+            return None;
+        }
+        self.last_sent = Some(json);
+        Some(OutgoingPlayPacket::PlayerListHeaderAndFooter {
+            header: Box::new(header),
+            footer: Box::new(footer),
+        })
+    }
+}
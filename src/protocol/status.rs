@@ -1,54 +1,30 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use futures::{TryStream, Sink, SinkExt, TryStreamExt};
 use mc_chat::ChatComponent;
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
-use crate::{util::{ProtocolError, Result}, io::{PacketReader, PacketWriter}, store::ServerStore, constants::ProtocolVersion};
+use crate::{util::{ProtocolError, Result}, store::ServerStore, constants::ProtocolVersion, config::MotdRotation, placeholders::Placeholders};
 
-use super::{PacketData, PacketPayload};
+use super::{Packet, PacketData, PacketPayload};
 
-enum IncomingStatusPacket {
+#[derive(Debug, Packet)]
+pub enum IncomingStatusPacket {
+    #[packet(id = 0x00)]
     Request,
+    #[packet(id = 0x01)]
     Ping(i64),
 }
 
-impl IncomingStatusPacket {
-    pub fn read<R: PacketReader>(packet_id: i32, rdr: &mut R) -> Result<Option<Self>> {
-        match packet_id {
-            0x00 => Ok(Some(Self::Request)),
-            0x01 => Ok(Some(Self::Ping(rdr.read_long()?))),
-            _ => Ok(None),
-        }
-    }
-}
-
-enum OutgoingStatusPacket {
-    Response(ServerListPingResponse),
+#[derive(Debug, Packet)]
+pub enum OutgoingStatusPacket {
+    #[packet(id = 0x00)]
+    Response(#[json] Box<ServerListPingResponse>),
+    #[packet(id = 0x01)]
     Pong(i64),
 }
 
-impl OutgoingStatusPacket {
-    pub fn write(&self) -> Result<PacketPayload> {
-        let mut payload = PacketPayload::new(self.packet_id());
-        match self {
-            Self::Response(response) => {
-                payload.write_json(response)?;
-            }
-            Self::Pong(v) => {
-                payload.write_long(*v)?;
-            }
-        }
-        Ok(payload)
-    }
-
-    fn packet_id(&self) -> i32 {
-        match self {
-            Self::Response(_) => 0x00,
-            Self::Pong(_) => 0x01,
-        }
-    }
-}
-
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ServerListPingResponse {
     version: ProtocolVersion,
@@ -58,10 +34,22 @@ pub struct ServerListPingResponse {
     favicon: Option<String>,
 }
 
+impl ServerListPingResponse {
+    pub fn max_players(&self) -> u32 {
+        self.players.max
+    }
+
+    pub fn set_favicon(&mut self, favicon: String) {
+        self.favicon = Some(favicon);
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ServerListPlayers {
     max: u32,
+    #[serde(default)]
     online: u32,
+    #[serde(default)]
     sample: Vec<SamplePlayer>,
 }
 
@@ -71,6 +59,53 @@ pub struct SamplePlayer {
     id: Uuid,
 }
 
+/// Same as the vanilla server
+const MAX_SAMPLE_PLAYERS: usize = 12;
+
+fn build_response(store: &ServerStore, server_address: &str) -> ServerListPingResponse {
+    let config = store.get_config();
+    let profile = store.get_profile(server_address);
+    let mut response = profile.status.clone();
+    let online_players = store.get_online_players();
+
+    response.players.online = online_players.len() as u32;
+    response.players.max = store.max_players(&profile);
+    response.players.sample = match &config.players.hover_text {
+        Some(lines) => lines
+            .iter()
+            .map(|line| SamplePlayer {
+                name: line.clone(),
+                id: Uuid::nil(),
+            })
+            .collect(),
+        None => online_players
+            .into_iter()
+            .take(MAX_SAMPLE_PLAYERS)
+            .map(|player| SamplePlayer {
+                name: player.username,
+                id: player.uuid,
+            })
+            .collect(),
+    };
+
+    let placeholders = Placeholders::new(store, &profile);
+    response.description = match &profile.motd {
+        Some(motd) if !motd.messages.is_empty() => {
+            let index = match motd.rotation {
+                MotdRotation::PerPing => store.next_motd_index(),
+                MotdRotation::Interval(secs) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("current time is before the unix epoch!?");
+                    (now.as_secs() / secs.max(1)) as usize
+                }
+            };
+            placeholders.apply(&motd.messages[index % motd.messages.len()])
+        }
+        _ => placeholders.apply(&response.description),
+    };
+
+    response
+}
+
 async fn send_status_packet<W: Sink<PacketPayload, Error = ProtocolError> + Unpin>(
     wr: &mut W,
     packet: OutgoingStatusPacket,
@@ -93,10 +128,11 @@ pub async fn handle<
 >(
     rdr: &mut R,
     wr: &mut W,
+    server_address: &str,
     store: ServerStore,
 ) -> Result<()> {
     if let IncomingStatusPacket::Request = recv_status_packet(rdr).await? {
-        send_status_packet(wr, OutgoingStatusPacket::Response(store.get_config().status.clone())).await?;
+        send_status_packet(wr, OutgoingStatusPacket::Response(Box::new(build_response(&store, server_address)))).await?;
     } else {
         return Err(ProtocolError::MissingRequest);
     }
@@ -0,0 +1,254 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+use mc_chat::ChatComponent;
+use serde::Deserialize;
+use tokio::time::interval;
+use uuid::Uuid;
+
+use crate::{
+    protocol::{forwarding::ProfileProperty, play::boss_bar::{BossBarColor, BossBarDivision}},
+    store::ServerStore,
+    upstream::UpstreamStatus,
+};
+
+/// Lets players back onto the upstream a few at a time once it recovers, so that it isn't overwhelmed
+#[derive(Debug, Deserialize)]
+pub struct QueueConfig {
+    /// How many players are sent to the upstream at once
+    #[serde(default = "default_release_batch")]
+    pub release_batch: usize,
+    /// Seconds between each batch
+    #[serde(default = "default_release_interval")]
+    pub release_interval: u64,
+    /// Players in earlier groups go ahead of those in later ones, and players in no group go last
+    #[serde(default)]
+    pub priority_groups: Vec<PriorityGroupConfig>,
+}
+
+fn default_release_batch() -> usize {
+    1
+}
+
+fn default_release_interval() -> u64 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PriorityGroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub uuids: Vec<Uuid>,
+    /// Players who have a profile property with this name forwarded by the proxy are also in the group,
+    /// so that a permissions plugin on the proxy can decide who gets priority
+    #[serde(default)]
+    pub property: Option<String>,
+}
+
+impl PriorityGroupConfig {
+    fn contains(&self, uuid: Uuid, properties: &[ProfileProperty]) -> bool {
+        self.uuids.contains(&uuid)
+            || matches!(&self.property, Some(name) if properties.iter().any(|p| &p.name == name))
+    }
+}
+
+/// Where a player goes in the queue, with lower values going first
+pub fn priority(config: Option<&QueueConfig>, uuid: Uuid, properties: &[ProfileProperty]) -> usize {
+    let groups = match config {
+        Some(config) => &config.priority_groups[..],
+        None => &[],
+    };
+    groups.iter().position(|g| g.contains(uuid, properties)).unwrap_or(groups.len())
+}
+
+/// Players waiting to be sent to the upstream, by session id
+#[derive(Debug, Default)]
+pub struct Queue {
+    /// Sorted by priority and then session id, which goes up as players arrive
+    entries: BTreeSet<(usize, u64)>,
+    priorities: HashMap<u64, usize>,
+}
+
+impl Queue {
+    pub fn push(&mut self, session_id: u64, priority: usize) {
+        if let Some(old) = self.priorities.insert(session_id, priority) {
+            self.entries.remove(&(old, session_id));
+        }
+        self.entries.insert((priority, session_id));
+    }
+
+    /// Returns whether the session was in the queue
+    pub fn remove(&mut self, session_id: u64) -> bool {
+        match self.priorities.remove(&session_id) {
+            Some(priority) => self.entries.remove(&(priority, session_id)),
+            None => false,
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<u64> {
+        let (_, session_id) = self.entries.pop_first()?;
+        self.priorities.remove(&session_id);
+        Some(session_id)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.priorities.clear();
+    }
+
+    /// The session's (1-based) place in line, if it's queued
+    pub fn position(&self, session_id: u64) -> Option<usize> {
+        let priority = *self.priorities.get(&session_id)?;
+        Some(self.entries.range(..(priority, session_id)).count() + 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Releases queued players in batches while the upstream is up
+pub async fn run_release(store: ServerStore) {
+    // Changes to the queue config only take effect after a restart
+    let config = store.get_config();
+    let config = match &config.queue {
+        Some(queue) if config.upstream.is_some() => queue,
+        _ => return,
+    };
+
+    let mut release_interval = interval(Duration::from_secs(config.release_interval.max(1)));
+    release_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        release_interval.tick().await;
+        if let UpstreamStatus::Up = store.get_upstream_status() {
+            let released = store.release_queued(config.release_batch);
+            if released > 0 {
+                info!("released {} queued players to the upstream", released);
+            }
+        }
+    }
+}
+
+/// How players are shown their place in the queue while they wait.
+/// Text can use `{position}` and `{queue_size}`, along with the usual placeholders.
+#[derive(Clone, Debug, Deserialize)]
+pub struct QueueDisplayConfig {
+    #[serde(default)]
+    pub boss_bar: Option<BossBarConfig>,
+    #[serde(default)]
+    pub action_bar: Option<ChatComponent>,
+    /// Seconds between updates
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: u64,
+}
+
+fn default_refresh_interval() -> u64 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BossBarConfig {
+    pub title: ChatComponent,
+    #[serde(default)]
+    pub color: BossBarColor,
+    #[serde(default)]
+    pub division: BossBarDivision,
+    #[serde(default)]
+    pub progress: BossBarProgress,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BossBarProgress {
+    /// Always filled this much, from 0 to 1
+    Fixed(f32),
+    /// Fills up as the player gets closer to the front of the queue
+    Queue,
+}
+
+impl Default for BossBarProgress {
+    fn default() -> Self {
+        Self::Fixed(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{priority, PriorityGroupConfig, Queue, QueueConfig};
+    use crate::protocol::forwarding::ProfileProperty;
+
+    #[test]
+    fn pops_in_priority_then_arrival_order() {
+        let mut queue = Queue::default();
+        queue.push(1, 1);
+        queue.push(2, 0);
+        queue.push(3, 1);
+        queue.push(4, 0);
+        assert_eq!(queue.position(2), Some(1));
+        assert_eq!(queue.position(4), Some(2));
+        assert_eq!(queue.position(1), Some(3));
+        assert_eq!(queue.position(3), Some(4));
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop_front()).collect();
+        assert_eq!(order, [2, 4, 1, 3]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn repushing_changes_priority() {
+        let mut queue = Queue::default();
+        queue.push(1, 1);
+        queue.push(2, 1);
+        queue.push(2, 0);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.position(2), Some(1));
+
+        assert!(queue.remove(2));
+        assert!(!queue.remove(2));
+        assert_eq!(queue.position(2), None);
+        assert_eq!(queue.position(1), Some(1));
+
+        queue.clear();
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn finds_priority_groups() {
+        let vip = Uuid::from_u128(1);
+        let config = QueueConfig {
+            release_batch: 1,
+            release_interval: 1,
+            priority_groups: vec![
+                PriorityGroupConfig {
+                    name: "vip".to_string(),
+                    uuids: vec![vip],
+                    property: None,
+                },
+                PriorityGroupConfig {
+                    name: "donor".to_string(),
+                    uuids: Vec::new(),
+                    property: Some("donor".to_string()),
+                },
+            ],
+        };
+        let donor = [ProfileProperty {
+            name: "donor".to_string(),
+            value: String::new(),
+            signature: None,
+        }];
+
+        assert_eq!(priority(Some(&config), vip, &donor), 0);
+        assert_eq!(priority(Some(&config), Uuid::from_u128(2), &donor), 1);
+        assert_eq!(priority(Some(&config), Uuid::from_u128(2), &[]), 2);
+        assert_eq!(priority(None, vip, &donor), 0);
+    }
+}
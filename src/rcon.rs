@@ -0,0 +1,157 @@
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    commands,
+    store::ServerStore,
+    util::{self, ProtocolError, Result},
+};
+
+/// Lets operator tools run console commands using the Source RCON protocol, like the vanilla server
+#[derive(Clone, Debug, Deserialize)]
+pub struct RconConfig {
+    pub address: SocketAddr,
+    pub password: String,
+}
+
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_EXEC_COMMAND: i32 = 2;
+const TYPE_AUTH_RESPONSE: i32 = 2;
+const TYPE_AUTH: i32 = 3;
+
+/// Same limits as the vanilla server. Responses longer than this are split across several packets.
+const MAX_INCOMING_PACKET_SIZE: i32 = 1460;
+const MAX_RESPONSE_BODY: usize = 4096;
+
+#[derive(Debug)]
+struct RconPacket {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+/// Accepts RCON connections on the configured address until the process exits
+pub async fn serve(store: ServerStore, config: RconConfig) {
+    if config.password.is_empty() {
+        error!("not starting RCON, since no password is set");
+        return;
+    }
+    let listener = match TcpListener::bind(config.address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("failed to start RCON on {}: {}", config.address, e);
+            return;
+        }
+    };
+    info!("Listening for RCON on {}", config.address);
+
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("failed to accept RCON connection: {}", e);
+                continue;
+            }
+        };
+        let store = store.clone();
+        let password = config.password.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &store, &password).await {
+                debug!(%peer_addr, "RCON connection ended: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, store: &ServerStore, password: &str) -> Result<()> {
+    let peer_addr = stream.peer_addr()?;
+    let (rd, mut wr) = stream.into_split();
+    let mut rd = BufReader::new(rd);
+    let mut authenticated = false;
+
+    while let Some(packet) = read_packet(&mut rd).await? {
+        match packet.kind {
+            TYPE_AUTH => {
+                authenticated = util::constant_time_eq(packet.body.as_bytes(), password.as_bytes());
+                if authenticated {
+                    info!(%peer_addr, "RCON client authenticated");
+                    write_packet(&mut wr, packet.id, TYPE_AUTH_RESPONSE, "").await?;
+                } else {
+                    // Like vanilla, clients only get one guess per connection
+                    warn!(%peer_addr, "RCON client sent the wrong password");
+                    write_packet(&mut wr, -1, TYPE_AUTH_RESPONSE, "").await?;
+                    break;
+                }
+            }
+            TYPE_EXEC_COMMAND if authenticated => {
+                info!(%peer_addr, command = %packet.body, "running RCON command");
+                let output = commands::execute_console(store, &packet.body).await;
+                write_response(&mut wr, packet.id, &output).await?;
+            }
+            TYPE_EXEC_COMMAND => write_packet(&mut wr, -1, TYPE_RESPONSE, "").await?,
+            kind => write_packet(&mut wr, packet.id, TYPE_RESPONSE, &format!("Unknown request {:x}", kind)).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a packet, or returns `None` once the client has disconnected
+async fn read_packet<R: AsyncReadExt + Unpin>(rd: &mut R) -> Result<Option<RconPacket>> {
+    let length = match rd.read_i32_le().await {
+        Ok(length) => length,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // The id, type and two null terminators
+    if !(10..=MAX_INCOMING_PACKET_SIZE).contains(&length) {
+        return Err(ProtocolError::InvalidRcon(format!("packet length {} out of range", length)));
+    }
+
+    let id = rd.read_i32_le().await?;
+    let kind = rd.read_i32_le().await?;
+    let mut body = vec![0; length as usize - 8];
+    rd.read_exact(&mut body).await?;
+    // Both the body's null terminator and the empty string after it
+    let end = body.iter().position(|b| *b == 0).unwrap_or(body.len());
+    body.truncate(end);
+
+    Ok(Some(RconPacket {
+        id,
+        kind,
+        body: String::from_utf8(body)?,
+    }))
+}
+
+async fn write_response<W: AsyncWriteExt + Unpin>(wr: &mut W, id: i32, output: &str) -> Result<()> {
+    if output.is_empty() {
+        return write_packet(wr, id, TYPE_RESPONSE, "").await;
+    }
+    let mut rest = output;
+    while !rest.is_empty() {
+        let mut split = rest.len().min(MAX_RESPONSE_BODY);
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        let (chunk, remaining) = rest.split_at(split);
+        write_packet(wr, id, TYPE_RESPONSE, chunk).await?;
+        rest = remaining;
+    }
+    Ok(())
+}
+
+async fn write_packet<W: AsyncWriteExt + Unpin>(wr: &mut W, id: i32, kind: i32, body: &str) -> Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 14);
+    packet.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&kind.to_le_bytes());
+    packet.extend_from_slice(body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    wr.write_all(&packet).await?;
+    Ok(())
+}
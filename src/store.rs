@@ -1,52 +1,582 @@
-use std::{sync::{atomic::{Ordering, AtomicI32}, Arc}, collections::HashMap};
+use std::{sync::{atomic::{Ordering, AtomicBool, AtomicI32, AtomicU64, AtomicUsize}, Arc, Mutex, RwLock as StdRwLock}, collections::BTreeMap, net::IpAddr, time::{Duration, SystemTime}};
 
-use tokio::sync::RwLock;
+use mc_chat::ChatComponent;
+use tokio::sync::{broadcast, mpsc};
 use uuid::Uuid;
 
-use crate::{config::Config, world::{map_template::{MapTemplate, BlockEntity}, chunk::Chunk}};
+use crate::{chat::{self, ChatFilter}, config::{self, Config}, metrics::{ConnectionState, Metrics}, player_data::PlayerData, constants::Gamemode, profile::{Profile, Profiles}, protocol::{forwarding::ProfileProperty, play::{ChatPosition, OutgoingPlayPacket}}, queue::{self, Queue}, upstream::UpstreamStatus, util::{self, Result}};
 
 #[derive(Clone, Debug)]
 pub struct ServerStore(Arc<StoreData>);
 
 #[derive(Debug)]
 struct StoreData {
-    config: Config,
-    chunks: Vec<Chunk>,
-    block_entities: Vec<BlockEntity>,
-    next_player_id: AtomicI32,
-    player_id_map: RwLock<HashMap<Uuid, i32>>,
+    // These are replaced when the config is reloaded
+    config: StdRwLock<Arc<Config>>,
+    profiles: StdRwLock<Profiles>,
+    /// What the server started with, which is still in effect for settings that are only read at startup
+    startup_config: Arc<Config>,
+    chat_filters: StdRwLock<Arc<Vec<Box<dyn ChatFilter>>>>,
+    /// Filters that were passed in rather than set up by the config, which run after those that were
+    extra_chat_filters: Vec<Box<dyn ChatFilter>>,
+    player_data: Option<PlayerData>,
+    metrics: Arc<Metrics>,
+    next_entity_id: AtomicI32,
+    next_session_id: AtomicU64,
+    // This is a std mutex so that entries can be removed when a SessionGuard is dropped.
+    // Keyed by session id, so iteration order is join order.
+    sessions: Mutex<BTreeMap<u64, Session>>,
+    /// Online players who haven't been sent to the upstream yet.
+    /// Only locked while `sessions` is locked, or on its own.
+    queue: Mutex<Queue>,
+    player_events: broadcast::Sender<PlayerEvent>,
+    player_moves: broadcast::Sender<PlayerMoved>,
+    upstream_status: StdRwLock<UpstreamStatus>,
+    motd_counter: AtomicUsize,
+    /// While set, new players are turned away
+    maintenance: AtomicBool,
+}
+
+/// A player currently in the play state
+#[derive(Clone, Debug)]
+pub struct OnlinePlayer {
+    pub uuid: Uuid,
+    pub username: String,
+    /// Forwarded by the proxy, if there is one
+    pub properties: Vec<ProfileProperty>,
+    pub gamemode: Gamemode,
+    /// In milliseconds, measured using keep alives
+    pub latency: i32,
+    pub display_name: Option<ChatComponent>,
+    /// Assigned when the session is registered
+    pub entity_id: i32,
+    /// Which profile's world the player is in
+    pub profile_id: usize,
+    /// Only known once the player has sent a movement packet
+    pub position: Option<Position>,
+}
+
+/// How a player connected
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionInfo {
+    /// The player's real address, as forwarded by the proxy if there is one
+    pub address: IpAddr,
+    pub protocol_version: i32,
+}
+
+/// A connection in the play state, as kept in the session registry
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: u64,
+    pub player: OnlinePlayer,
+    pub connection: ConnectionInfo,
+    pub joined_at: SystemTime,
+    outbound: mpsc::Sender<SessionMessage>,
+    /// The latency everyone else was last told about
+    broadcast_latency: i32,
+}
+
+impl Session {
+    /// Passes a message to the session's connection, returning false if it's too far behind or has already gone
+    pub fn send(&self, message: SessionMessage) -> bool {
+        self.outbound.try_send(message).is_ok()
+    }
+
+    /// Like `send`, but waits up to `timeout` for room if the connection is behind
+    pub async fn send_within(&self, message: SessionMessage, timeout: Duration) -> bool {
+        matches!(tokio::time::timeout(timeout, self.outbound.send(message)).await, Ok(Ok(())))
+    }
+}
+
+/// Something for a session's connection to do, sent from elsewhere in the server
+#[derive(Clone, Debug)]
+pub enum SessionMessage {
+    Packet(Box<OutgoingPlayPacket>),
+    /// Send the player to the upstream server
+    Transfer,
+    Disconnect(Box<ChatComponent>),
+    /// The server is stopping, so the player should be moved elsewhere or disconnected
+    Shutdown,
+}
+
+/// Messages are small and handled quickly, so a session this far behind is probably stuck
+const SESSION_MESSAGE_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+/// Changes to the online player list, sent to every player so that they can stay up to date
+#[derive(Clone, Debug)]
+pub enum PlayerEvent {
+    Joined(Box<OnlinePlayer>),
+    Left {
+        uuid: Uuid,
+        profile_id: usize,
+    },
+    LatencyChanged {
+        uuid: Uuid,
+        profile_id: usize,
+        latency: i32,
+    },
+}
+
+/// A player moving, which is kept apart from `PlayerEvent`s so that a flood of movement can't push joins and leaves out
+#[derive(Clone, Debug)]
+pub struct PlayerMoved {
+    pub uuid: Uuid,
+    pub entity_id: i32,
+    pub profile_id: usize,
+    pub position: Position,
+}
+
+/// Receivers that fall this far behind have to resync with the session registry
+const PLAYER_EVENT_CAPACITY: usize = 256;
+
+/// Movement is up to 20 per player per second. Missing some isn't the end of the world, since the next one catches up.
+const PLAYER_MOVE_CAPACITY: usize = 1024;
+
+/// Keeps a session in the registry until dropped
+#[derive(Debug)]
+pub struct SessionGuard {
+    store: ServerStore,
+    session_id: u64,
+    entity_id: i32,
+    username: String,
+}
+
+impl SessionGuard {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub fn entity_id(&self) -> i32 {
+        self.entity_id
+    }
+
+    /// Records the player's latency. Other players aren't told about it until `broadcast_latency` is called.
+    pub fn set_latency(&self, latency: i32) {
+        let mut sessions = self.store.0.sessions.lock().expect("sessions lock poisoned");
+        if let Some(session) = sessions.get_mut(&self.session_id) {
+            session.player.latency = latency;
+        }
+    }
+
+    /// Tells everyone else the player's latency, if it's changed since they were last told.
+    /// This is a separate step so that latency can be measured often without flooding everyone's tab list.
+    pub fn broadcast_latency(&self) {
+        let mut sessions = self.store.0.sessions.lock().expect("sessions lock poisoned");
+        if let Some(session) = sessions.get_mut(&self.session_id) {
+            let player = &session.player;
+            if session.broadcast_latency != player.latency {
+                session.broadcast_latency = player.latency;
+                // It's fine for nobody to be listening
+                let _ = self.store.0.player_events.send(PlayerEvent::LatencyChanged {
+                    uuid: player.uuid,
+                    profile_id: player.profile_id,
+                    latency: player.latency,
+                });
+            }
+        }
+    }
+
+    /// Returns the player's (1-based) place in line and how many players are waiting in total,
+    /// or `None` once they've been released
+    pub fn queue_position(&self) -> Option<(usize, usize)> {
+        let queue = self.store.0.queue.lock().expect("queue lock poisoned");
+        queue.position(self.session_id).map(|position| (position, queue.len()))
+    }
+
+    /// The player's last known position, once they've moved
+    pub fn position(&self) -> Option<Position> {
+        let sessions = self.store.0.sessions.lock().expect("sessions lock poisoned");
+        sessions.get(&self.session_id).and_then(|s| s.player.position)
+    }
+
+    pub fn set_position(&self, position: Position) {
+        let mut sessions = self.store.0.sessions.lock().expect("sessions lock poisoned");
+        if let Some(player) = sessions.get_mut(&self.session_id).map(|s| &mut s.player) {
+            if player.position != Some(position) {
+                player.position = Some(position);
+                let _ = self.store.0.player_moves.send(PlayerMoved {
+                    uuid: player.uuid,
+                    entity_id: player.entity_id,
+                    profile_id: player.profile_id,
+                    position,
+                });
+            }
+        }
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut sessions = self.store.0.sessions.lock().expect("sessions lock poisoned");
+        if let Some(session) = sessions.remove(&self.session_id) {
+            let _ = self.store.0.player_events.send(PlayerEvent::Left {
+                uuid: session.player.uuid,
+                profile_id: session.player.profile_id,
+            });
+        }
+        self.store.0.metrics.connection_state_changed(ConnectionState::Play, ConnectionState::Login);
+        self.store.0.queue.lock().expect("queue lock poisoned").remove(self.session_id);
+    }
 }
 
 impl ServerStore {
-    pub fn new(config: Config, map: MapTemplate) -> Self {
+    pub fn new(
+        config: Config,
+        profiles: Profiles,
+        extra_chat_filters: Vec<Box<dyn ChatFilter>>,
+        player_data: Option<PlayerData>,
+    ) -> Self {
+        let config = Arc::new(config);
         Self(Arc::new(StoreData {
-            config,
-            block_entities: map.block_entities.clone(),
-            chunks: map.into_chunks(),
-            next_player_id: AtomicI32::new(0),
-            player_id_map: RwLock::new(HashMap::new()),
+            chat_filters: StdRwLock::new(Arc::new(chat::default_filters(&config.chat))),
+            config: StdRwLock::new(Arc::clone(&config)),
+            startup_config: config,
+            profiles: StdRwLock::new(profiles),
+            extra_chat_filters,
+            player_data,
+            metrics: Arc::default(),
+            next_entity_id: AtomicI32::new(0),
+            next_session_id: AtomicU64::new(0),
+            sessions: Mutex::new(BTreeMap::new()),
+            queue: Mutex::new(Queue::default()),
+            player_events: broadcast::channel(PLAYER_EVENT_CAPACITY).0,
+            player_moves: broadcast::channel(PLAYER_MOVE_CAPACITY).0,
+            upstream_status: StdRwLock::new(UpstreamStatus::Unknown),
+            motd_counter: AtomicUsize::new(0),
+            maintenance: AtomicBool::new(false),
         }))
     }
 
-    pub fn get_config(&self) -> &Config {
-        &self.0.config
+    /// The current config. Anything holding on to it keeps seeing the old one after a reload.
+    pub fn get_config(&self) -> Arc<Config> {
+        Arc::clone(&self.0.config.read().expect("config lock poisoned"))
+    }
+
+    /// Reads the config file again and reloads the maps it points to. New connections use the new config,
+    /// while players who are already online stay in the world they joined.
+    /// Returns the settings that changed but need a restart to take effect, which keep their old values until then.
+    /// This blocks while maps are loaded, so it shouldn't be called directly from async code.
+    pub fn reload_config(&self) -> Result<Vec<&'static str>> {
+        self.apply_config(config::read_config()?)
+    }
+
+    fn apply_config(&self, config: Config) -> Result<Vec<&'static str>> {
+        let profiles = Profiles::load(&config, Some(&self.0.profiles.read().expect("profiles lock poisoned")))?;
+        let chat_filters = chat::default_filters(&config.chat);
+        let restart_required = self.0.startup_config.restart_required(&config);
+        if !restart_required.is_empty() {
+            warn!("changes to {} only take effect after a restart", restart_required.join(", "));
+        }
+        *self.0.profiles.write().expect("profiles lock poisoned") = profiles;
+        *self.0.chat_filters.write().expect("chat filters lock poisoned") = Arc::new(chat_filters);
+        *self.0.config.write().expect("config lock poisoned") = Arc::new(config);
+        Ok(restart_required)
+    }
+
+    /// Runs a chat message through every filter, returning the message to send or `None` if it should be dropped
+    pub fn filter_chat(&self, username: &str, mut message: String) -> Option<String> {
+        let chat_filters = Arc::clone(&self.0.chat_filters.read().expect("chat filters lock poisoned"));
+        for filter in chat_filters.iter().chain(&self.0.extra_chat_filters) {
+            message = filter.filter(username, message)?;
+        }
+        Some(message)
+    }
+
+    /// Saved player records, if `player_data_file` is set
+    pub fn get_player_data(&self) -> Option<&PlayerData> {
+        self.0.player_data.as_ref()
+    }
+
+    pub fn get_metrics(&self) -> &Arc<Metrics> {
+        &self.0.metrics
     }
 
-    pub fn get_chunks(&self) -> &[Chunk] {
-        &self.0.chunks
+    /// Sends a chat message to everyone in the given profile's world
+    pub fn broadcast_chat(&self, profile_id: usize, sender: Uuid, message: ChatComponent) {
+        let packet = OutgoingPlayPacket::ChatMessage {
+            message,
+            position: ChatPosition::Chat,
+            sender,
+        };
+        self.broadcast_where(|s| s.player.profile_id == profile_id, SessionMessage::Packet(Box::new(packet)));
     }
 
-    pub fn get_block_entities(&self) -> &[BlockEntity] {
-        &self.0.block_entities
+    /// Sends a message to every session, returning how many it was sent to
+    pub fn broadcast(&self, message: SessionMessage) -> usize {
+        self.broadcast_where(|_| true, message)
     }
 
-    pub async fn get_player_id(&self, uuid: Uuid) -> i32 {
-        let id = self.0.player_id_map.read().await.get(&uuid).cloned();
-        if let Some(id) = id {
-            return id;
+    /// Sends a message to every session, waiting up to `timeout` for any that are behind rather than skipping them.
+    /// Returns how many sessions it couldn't be sent to.
+    pub async fn broadcast_within(&self, message: SessionMessage, timeout: Duration) -> usize {
+        let sessions = self.get_sessions();
+        let sent = futures::future::join_all(sessions.iter().map(|s| s.send_within(message.clone(), timeout))).await;
+        sent.into_iter().filter(|sent| !sent).count()
+    }
+
+    /// Sends a message to every session that matches `predicate`, returning how many it was sent to.
+    /// Sessions that are too far behind miss out, rather than holding everyone else up.
+    pub fn broadcast_where(&self, predicate: impl Fn(&Session) -> bool, message: SessionMessage) -> usize {
+        let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        sessions
+            .values()
+            .filter(|s| predicate(s))
+            .filter(|s| s.send(message.clone()))
+            .count()
+    }
+
+    /// Sends a message to every session belonging to the given player, returning whether they were online
+    pub fn send_to(&self, uuid: Uuid, message: SessionMessage) -> bool {
+        self.broadcast_where(|s| s.player.uuid == uuid, message) > 0
+    }
+
+    /// Picks the profile for the server address a player sent in their handshake
+    pub fn get_profile(&self, server_address: &str) -> Arc<Profile> {
+        let profiles = self.0.profiles.read().expect("profiles lock poisoned");
+        profiles.get(&util::normalize_server_address(server_address))
+    }
+
+    /// Registers a session for a player who's finished logging in, unless the configured connection cap has been reached.
+    /// The player's entity ID is assigned here, and they're added to the back of their queue priority group.
+    /// The session stays registered until the returned guard is dropped, and messages for it arrive on the returned receiver.
+    pub fn try_register_session(
+        &self,
+        mut player: OnlinePlayer,
+        connection: ConnectionInfo,
+    ) -> Option<(SessionGuard, mpsc::Receiver<SessionMessage>)> {
+        let mut sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        let config = self.get_config();
+        if let Some(max) = config.players.max_connections {
+            if sessions.len() >= max as usize {
+                return None;
+            }
+        }
+        let session_id = self.0.next_session_id.fetch_add(1, Ordering::Relaxed);
+        player.entity_id = self.0.next_entity_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self.0.player_events.send(PlayerEvent::Joined(Box::new(player.clone())));
+
+        let guard = SessionGuard {
+            store: self.clone(),
+            session_id,
+            entity_id: player.entity_id,
+            username: player.username.clone(),
+        };
+        if self.is_queue_enabled() {
+            let priority = queue::priority(config.queue.as_ref(), player.uuid, &player.properties);
+            self.0.queue.lock().expect("queue lock poisoned").push(session_id, priority);
+        }
+
+        // Login is the state the connection came from, which it goes back to until it's closed
+        self.0.metrics.connection_state_changed(ConnectionState::Login, ConnectionState::Play);
+        let (outbound, receiver) = mpsc::channel(SESSION_MESSAGE_CAPACITY);
+        sessions.insert(session_id, Session {
+            id: session_id,
+            connection,
+            joined_at: SystemTime::now(),
+            broadcast_latency: player.latency,
+            player,
+            outbound,
+        });
+        Some((guard, receiver))
+    }
+
+    /// Returns the players currently online, in the order they joined
+    pub fn get_online_players(&self) -> Vec<OnlinePlayer> {
+        self.0.sessions.lock().expect("sessions lock poisoned").values().map(|s| s.player.clone()).collect()
+    }
+
+    /// Returns every registered session, in the order they joined
+    pub fn get_sessions(&self) -> Vec<Session> {
+        self.0.sessions.lock().expect("sessions lock poisoned").values().cloned().collect()
+    }
+
+    /// Finds a player's session. If they're somehow connected more than once, this is the earliest one.
+    pub fn get_session(&self, uuid: Uuid) -> Option<Session> {
+        let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        sessions.values().find(|s| s.player.uuid == uuid).cloned()
+    }
+
+    /// Finds an online player by UUID or (case-insensitive) username
+    pub fn find_session(&self, player: &str) -> Option<Session> {
+        match player.parse::<Uuid>() {
+            Ok(uuid) => self.get_session(uuid),
+            Err(_) => {
+                let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+                sessions.values().find(|s| s.player.username.eq_ignore_ascii_case(player)).cloned()
+            }
+        }
+    }
+
+    /// Subscribes to changes to the online player list. Subscribe before calling `get_online_players`,
+    /// so that nothing is missed in between.
+    pub fn subscribe_player_events(&self) -> broadcast::Receiver<PlayerEvent> {
+        self.0.player_events.subscribe()
+    }
+
+    pub fn subscribe_player_moves(&self) -> broadcast::Receiver<PlayerMoved> {
+        self.0.player_moves.subscribe()
+    }
+
+    /// The (1-based) place in line of the given player, if they're online and still queued
+    pub fn queue_position(&self, uuid: Uuid) -> Option<usize> {
+        let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        let queue = self.0.queue.lock().expect("queue lock poisoned");
+        sessions
+            .values()
+            .filter(|s| s.player.uuid == uuid)
+            .find_map(|s| queue.position(s.id))
+    }
+
+    /// Whether players are queued at all, which needs both `queue` and `upstream` to have been set at startup,
+    /// since that's when the task releasing them from the queue is started
+    pub fn is_queue_enabled(&self) -> bool {
+        let config = &self.0.startup_config;
+        config.queue.is_some() && config.upstream.is_some()
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.0.queue.lock().expect("queue lock poisoned").len()
+    }
+
+    /// Takes up to `count` players off the front of the queue and tells their connections to send them
+    /// to the upstream. Returns how many were released.
+    pub fn release_queued(&self, count: usize) -> usize {
+        let released: Vec<_> = {
+            let mut queue = self.0.queue.lock().expect("queue lock poisoned");
+            std::iter::from_fn(|| queue.pop_front()).take(count).collect()
+        };
+        let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        for session_id in &released {
+            if let Some(session) = sessions.get(session_id) {
+                if !session.send(SessionMessage::Transfer) {
+                    warn!(uuid = %session.player.uuid, "failed to release player from the queue");
+                }
+            }
         }
-        let id = self.0.next_player_id.fetch_add(1, Ordering::Relaxed);
-        self.0.player_id_map.write().await.insert(uuid, id);
-        id
+        released.len()
+    }
+
+    /// Sends everyone to the upstream straight away, including players still in the queue.
+    /// Returns how many players were sent.
+    pub fn transfer_all(&self) -> usize {
+        let sessions = self.0.sessions.lock().expect("sessions lock poisoned");
+        self.0.queue.lock().expect("queue lock poisoned").clear();
+        sessions.values().filter(|s| s.send(SessionMessage::Transfer)).count()
+    }
+
+    pub fn online_player_count(&self) -> usize {
+        self.0.sessions.lock().expect("sessions lock poisoned").len()
+    }
+
+    /// The max player count shown to players, either the connection cap or the profile's configured value
+    pub fn max_players(&self, profile: &Profile) -> u32 {
+        self.get_config().players.max_connections.unwrap_or_else(|| profile.status.max_players())
+    }
+
+    pub fn get_upstream_status(&self) -> UpstreamStatus {
+        *self.0.upstream_status.read().expect("upstream status lock poisoned")
+    }
+
+    pub fn set_upstream_status(&self, status: UpstreamStatus) {
+        *self.0.upstream_status.write().expect("upstream status lock poisoned") = status;
+    }
+
+    pub fn is_maintenance(&self) -> bool {
+        self.0.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Turns maintenance mode on or off. Players who are already online aren't affected.
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.0.maintenance.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns a counter that goes up by one every time it's called, used for rotating MOTDs
+    pub fn next_motd_index(&self) -> usize {
+        self.0.motd_counter.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use uuid::Uuid;
+
+    use super::{ConnectionInfo, OnlinePlayer, ServerStore};
+    use crate::{config::Config, constants::{Gamemode, PROTOCOL_VERSION}, profile::Profiles};
+
+    fn sample_config() -> Config {
+        serde_json::from_str(include_str!("../sample_config.json")).expect("invalid sample config")
+    }
+
+    fn test_store(config: Config) -> ServerStore {
+        let profiles = Profiles::load(&config, None).unwrap();
+        ServerStore::new(config, profiles, Vec::new(), None)
+    }
+
+    fn test_player(uuid: u128) -> (OnlinePlayer, ConnectionInfo) {
+        let player = OnlinePlayer {
+            uuid: Uuid::from_u128(uuid),
+            username: format!("player{}", uuid),
+            properties: Vec::new(),
+            gamemode: Gamemode::Adventure,
+            latency: 0,
+            display_name: None,
+            entity_id: 0,
+            profile_id: 0,
+            position: None,
+        };
+        let connection = ConnectionInfo {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            protocol_version: PROTOCOL_VERSION,
+        };
+        (player, connection)
+    }
+
+    #[test]
+    fn only_queues_players_when_the_queue_is_enabled() {
+        let store = test_store(sample_config());
+        let (player, connection) = test_player(1);
+        let (session, _messages) = store.try_register_session(player, connection).unwrap();
+        assert_eq!(session.queue_position(), Some((1, 1)));
+
+        let mut config = sample_config();
+        config.queue = None;
+        let store = test_store(config);
+        let (player, connection) = test_player(1);
+        let (session, _messages) = store.try_register_session(player, connection).unwrap();
+        assert_eq!(session.queue_position(), None);
+        assert_eq!(store.queue_len(), 0);
+    }
+
+    #[test]
+    fn reloading_keeps_profiles_the_same() {
+        let store = test_store(sample_config());
+        let before = [store.get_profile("localhost").id, store.get_profile("play.example.com").id];
+        assert_ne!(before[0], before[1]);
+
+        // Players who join after this should end up in the same profiles as those who joined before it
+        store.apply_config(sample_config()).unwrap();
+        let after = [store.get_profile("localhost").id, store.get_profile("play.example.com").id];
+        assert_eq!(before, after);
+
+        let mut config = sample_config();
+        config.virtual_hosts.clear();
+        store.apply_config(config).unwrap();
+        assert_eq!(store.get_profile("play.example.com").id, before[0]);
     }
 }
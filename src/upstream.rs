@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, TryStreamExt};
+use mc_chat::ChatComponent;
+use serde::Deserialize;
+use tokio::{net::TcpStream, time::{interval, timeout}};
+use tokio_util::codec::Framed;
+use uuid::Uuid;
+
+use crate::{
+    constants::PROTOCOL_VERSION,
+    protocol::{
+        handshake::HandshakePacket,
+        play::{ChatPosition, OutgoingPlayPacket},
+        status::{IncomingStatusPacket, OutgoingStatusPacket},
+        MinecraftFramedCodec, Packet, ProtocolState,
+    },
+    store::{ServerStore, SessionMessage},
+    util::{ProtocolError, Result},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamConfig {
+    /// Address of the main server, as `host:port`
+    pub address: String,
+    /// Seconds between health checks
+    #[serde(default = "default_check_interval")]
+    pub check_interval: u64,
+    /// What the proxy calls the main server, so that players can be sent there with a BungeeCord plugin message.
+    /// Without this, players are disconnected so that they can reconnect themselves.
+    #[serde(default)]
+    pub proxy_server: Option<String>,
+    /// Told to everyone online when the upstream comes back up
+    #[serde(default)]
+    pub up_message: Option<ChatComponent>,
+    /// Told to everyone online when the upstream goes down
+    #[serde(default)]
+    pub down_message: Option<ChatComponent>,
+}
+
+fn default_check_interval() -> u64 {
+    5
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum UpstreamStatus {
+    /// No health check has completed yet, or health checks are disabled
+    Unknown,
+    Up,
+    Down {
+        since: Instant,
+    },
+}
+
+impl UpstreamStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpstreamStatus::Unknown => "unknown",
+            UpstreamStatus::Up => "online",
+            UpstreamStatus::Down { .. } => "offline",
+        }
+    }
+
+    /// How long the upstream has been down for, if it's down
+    pub fn downtime(&self) -> Option<Duration> {
+        match self {
+            UpstreamStatus::Down { since } => Some(since.elapsed()),
+            _ => None,
+        }
+    }
+}
+
+/// Periodically pings the upstream server and keeps the store's upstream status up to date
+pub async fn run_health_check(store: ServerStore) {
+    // Changes to the address and check interval only take effect after a restart
+    let config = store.get_config();
+    let config = match &config.upstream {
+        Some(config) => config,
+        None => return,
+    };
+
+    let mut check_interval = interval(Duration::from_secs(config.check_interval.max(1)));
+    check_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        check_interval.tick().await;
+
+        let result = match timeout(Duration::from_secs(5), ping(&config.address)).await {
+            Ok(result) => result,
+            Err(_) => Err(ProtocolError::TimedOut),
+        };
+
+        match (store.get_upstream_status(), result) {
+            (UpstreamStatus::Up, Ok(())) | (UpstreamStatus::Down { .. }, Err(_)) => {}
+            (_, Ok(())) => {
+                info!(address = %config.address, "upstream server is up");
+                store.set_upstream_status(UpstreamStatus::Up);
+                announce(&store, |upstream| &upstream.up_message);
+            }
+            (status, Err(e)) => {
+                warn!(address = %config.address, "upstream server is down: {}", e);
+                store.set_upstream_status(UpstreamStatus::Down { since: Instant::now() });
+                // Players who joined before the first check already know it's down
+                if let UpstreamStatus::Up = status {
+                    announce(&store, |upstream| &upstream.down_message);
+                }
+            }
+        }
+    }
+}
+
+/// Tells everyone online about a status change, using the message from the latest config
+fn announce(store: &ServerStore, message: impl Fn(&UpstreamConfig) -> &Option<ChatComponent>) {
+    let config = store.get_config();
+    if let Some(message) = config.upstream.as_ref().and_then(|upstream| message(upstream).as_ref()) {
+        let packet = OutgoingPlayPacket::ChatMessage {
+            message: message.clone(),
+            position: ChatPosition::System,
+            sender: Uuid::nil(),
+        };
+        store.broadcast(SessionMessage::Packet(Box::new(packet)));
+    }
+}
+
+/// Performs a server list ping against the given server
+async fn ping(address: &str) -> Result<()> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().unwrap_or(25565)),
+        None => (address, 25565),
+    };
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let mut framed = Framed::new(stream, MinecraftFramedCodec::new(None));
+
+    framed.send(HandshakePacket {
+        protocol_version: PROTOCOL_VERSION,
+        server_address: host.to_string(),
+        server_port: port,
+        next_state: ProtocolState::Status,
+    }.write()?).await?;
+    framed.send(IncomingStatusPacket::Request.write()?).await?;
+
+    let mut packet = framed.try_next().await?.ok_or(ProtocolError::NoPacket)?;
+    match OutgoingStatusPacket::read(packet.packet_id, &mut packet)? {
+        Some(OutgoingStatusPacket::Response(_)) => Ok(()),
+        _ => Err(ProtocolError::InvalidPacketId(packet.packet_id)),
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use uuid::Uuid;
 
 #[derive(Debug, thiserror::Error)]
@@ -23,7 +25,19 @@ pub enum ProtocolError {
     #[error("json error: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("no packet")]
-    NoPacket
+    NoPacket,
+    #[error("timed out")]
+    TimedOut,
+    #[error("invalid chunk data: {0}")]
+    InvalidChunk(String),
+    #[error("invalid favicon: {0}")]
+    InvalidFavicon(String),
+    #[error("invalid forwarding data: {0}")]
+    InvalidForwarding(String),
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("invalid rcon packet: {0}")]
+    InvalidRcon(String),
 }
 
 pub type Result<T> = std::result::Result<T, ProtocolError>;
@@ -33,3 +47,42 @@ pub fn offline_mode_uuid(username: &str) -> Uuid {
     // offline mode uuids, but its close enough lol
     Uuid::new_v3(&Uuid::NAMESPACE_OID, username.as_bytes())
 }
+
+/// Formats a duration for display to players, ie. `1h 5m`, `5m 3s` or `12s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs / 60) % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Strips anything that isn't part of the hostname from a handshake's server address,
+/// like the markers Forge appends (`\0FML\0`) and the trailing dot of a fully qualified name
+pub fn normalize_server_address(address: &str) -> String {
+    let host = address.split('\0').next().unwrap_or_default();
+    host.trim_end_matches('.').to_lowercase()
+}
+
+/// Compares secrets without bailing out at the first difference, so that timing doesn't give away how much of a guess was right.
+/// The length can still be told apart.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::constant_time_eq;
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"hunter2", b"hunter2"));
+        assert!(!constant_time_eq(b"hunter2", b"hunter3"));
+        assert!(!constant_time_eq(b"hunter2", b"hunter"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -1,5 +1,28 @@
+use std::path::Path;
+
+use crate::util::Result;
+
+use self::{chunk::Chunk, map_template::BlockEntity};
+
 pub mod dimension;
 pub mod map_template;
 pub mod chunk;
 pub mod block_ids;
 pub mod packed_array;
+
+/// A map that's been loaded and converted into chunks, ready to send to players
+#[derive(Debug, PartialEq)]
+pub struct World {
+    pub chunks: Vec<Chunk>,
+    pub block_entities: Vec<BlockEntity>,
+}
+
+impl World {
+    pub fn load(path: &Path) -> Result<Self> {
+        let template = map_template::load_template(path)?;
+        Ok(Self {
+            block_entities: template.block_entities.clone(),
+            chunks: template.into_chunks(),
+        })
+    }
+}
@@ -13,6 +13,20 @@ lazy_static::lazy_static! {
         const BLOCK_ENTITIES: &str = include_str!("block_entities.json");
         serde_json::from_str(BLOCK_ENTITIES).expect("failed to parse block_entities.json")
     };
+
+    // Only needed when decoding packets we've sent (ie. when inspecting captures), so built lazily from BLOCK_DATA
+    static ref BLOCK_STATES_BY_ID: Map<i32, BlockState> = {
+        let mut states = Map::new();
+        for (name, block) in BLOCK_DATA.iter() {
+            for state in &block.states {
+                states.insert(state.id, BlockState {
+                    name: name.clone(),
+                    properties: state.properties.clone(),
+                });
+            }
+        }
+        states
+    };
 }
 
 #[derive(Deserialize)]
@@ -44,3 +58,11 @@ pub fn get_state_id(blockstate: &BlockState) -> Option<i32> {
 pub fn get_block_entity_id(be: &str) -> Option<i32> {
     BLOCK_ENTITY_DATA.get(be).cloned()
 }
+
+pub fn get_block_state(id: i32) -> Option<BlockState> {
+    BLOCK_STATES_BY_ID.get(&id).cloned()
+}
+
+pub fn get_block_entity_name(id: i32) -> Option<&'static str> {
+    BLOCK_ENTITY_DATA.iter().find(|(_, v)| **v == id).map(|(k, _)| k.as_str())
+}
@@ -1,10 +1,10 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{io::PacketWriter, util::Result};
+use crate::{io::{PacketField, PacketReader, PacketWriter}, util::{ProtocolError, Result}};
 
 use super::{map_template::BlockState, packed_array::PackedBitArray, block_ids};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Chunk {
     pub x: i32,
     pub z: i32,
@@ -18,9 +18,79 @@ impl Chunk {
         }
         Ok(())
     }
+
+    /// Reads chunk sections back out of the data array of a chunk data packet
+    pub fn read(x: i32, z: i32, data: &[u8]) -> Result<Self> {
+        let mut rdr = std::io::Cursor::new(data);
+        let mut sections = Vec::new();
+        while (rdr.position() as usize) < data.len() {
+            sections.push(ChunkSection::read(sections.len() as i32, &mut rdr)?);
+        }
+        Ok(Self {
+            x,
+            z,
+            sections,
+        })
+    }
+}
+
+/// Chunks are written the way they appear in the chunk data packet, with empty lighting
+impl PacketField for Chunk {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let x = rdr.read_int()?;
+        let z = rdr.read_int()?;
+        let _: Heightmaps = rdr.read_nbt()?;
+        let length = rdr.read_var_int()?;
+        let data = rdr.read_bytes(length.max(0) as usize)?;
+        rdr.read_var_int()?; // block entities, which we always send separately
+        LightData::read_field(rdr)?;
+        Chunk::read(x, z, &data)
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        let mut heightmap = vec![0x0100804020100804; 36];
+        heightmap.push(0x0000000020100804);
+        wr.write_int(self.x)?;
+        wr.write_int(self.z)?;
+        wr.write_nbt(&Heightmaps {
+            motion_blocking: heightmap,
+        })?;
+        let mut data = Vec::<u8>::new();
+        self.write(&mut data)?;
+        wr.write_var_int(data.len() as i32)?;
+        wr.write_bytes(&data)?;
+        wr.write_var_int(0)?; // block entities
+        LightData::default().write_field(wr)
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PacketField)]
+pub struct LightData {
+    // TODO: true or false? does it matter?
+    pub trust_edges: bool,
+    pub sky_light_mask: Vec<u64>,
+    pub block_light_mask: Vec<u64>,
+    pub empty_sky_light_mask: Vec<u64>,
+    pub empty_block_light_mask: Vec<u64>,
+    pub sky_light: Vec<Vec<u8>>,
+    pub block_light: Vec<Vec<u8>>,
+}
+
+impl Default for LightData {
+    fn default() -> Self {
+        Self {
+            trust_edges: true,
+            sky_light_mask: vec![],
+            block_light_mask: vec![],
+            empty_sky_light_mask: vec![],
+            empty_block_light_mask: vec![],
+            sky_light: vec![],
+            block_light: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct ChunkSection {
     pub y_pos: i32,
     pub block_count: u16,
@@ -73,10 +143,83 @@ impl ChunkSection {
         
         Ok(())
     }
+
+    pub fn read<R: PacketReader>(y_pos: i32, rdr: &mut R) -> Result<Self> {
+        let block_count = rdr.read_ushort()?;
+
+        let bits_per_entry = rdr.read_ubyte()?;
+        let palette = if bits_per_entry == 0 {
+            vec![rdr.read_var_int()?]
+        } else {
+            let length = rdr.read_var_int()?;
+            // A section only has 4096 blocks, so a longer palette can only come from bad data
+            let mut palette = Vec::with_capacity(length.clamp(0, 4096) as usize);
+            for _ in 0..length {
+                palette.push(rdr.read_var_int()?);
+            }
+            palette
+        };
+        let states = Vec::<u64>::read_field(rdr)?;
+
+        let mut block_states = Vec::with_capacity(4096);
+        if bits_per_entry == 0 {
+            let state = block_ids::get_block_state(palette[0]).ok_or(ProtocolError::InvalidEnumValue(palette[0]))?;
+            block_states.resize(4096, state);
+        } else {
+            let states = PackedBitArray::from_raw(states, bits_per_entry as usize).ok_or_else(|| {
+                ProtocolError::InvalidChunk(format!("block states don't match {} bits per entry", bits_per_entry))
+            })?;
+            for i in 0..4096 {
+                let state_id = *palette.get(states.get_value(i) as usize).ok_or(ProtocolError::InvalidEnumValue(i as i32))?;
+                block_states.push(block_ids::get_block_state(state_id).ok_or(ProtocolError::InvalidEnumValue(state_id))?);
+            }
+        }
+
+        // Biomes, which we don't keep track of
+        if rdr.read_ubyte()? == 0 {
+            rdr.read_var_int()?;
+        } else {
+            let length = rdr.read_var_int()?;
+            for _ in 0..length {
+                rdr.read_var_int()?;
+            }
+        }
+        Vec::<u64>::read_field(rdr)?;
+
+        Ok(Self {
+            y_pos,
+            block_count,
+            block_states,
+        })
+    }
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Heightmaps {
     #[serde(rename = "MOTION_BLOCKING")]
     pub motion_blocking: Vec<i64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn rejects_states_that_dont_match_bits_per_entry() {
+        let mut data = Vec::new();
+        data.write_ushort(4096).unwrap();
+        data.write_ubyte(4).unwrap();
+        data.write_var_int(1).unwrap();
+        data.write_var_int(0).unwrap();
+        // 4 bits per entry needs 256 longs
+        data.write_var_int(3).unwrap();
+        for _ in 0..3 {
+            data.write_ulong(0).unwrap();
+        }
+
+        let result = ChunkSection::read(0, &mut Cursor::new(data));
+        assert!(matches!(result, Err(ProtocolError::InvalidChunk(_))));
+    }
+}
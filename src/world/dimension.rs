@@ -42,6 +42,13 @@ pub struct DimensionType {
     has_ceiling: bool,
 }
 
+impl DimensionType {
+    /// The lowest Y that blocks can be at
+    pub fn min_y(&self) -> i32 {
+        self.min_y
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Biome {
     precipitation: String,
@@ -3,9 +3,9 @@ use std::{path::Path, fs::File};
 use nbt::{Map, Value};
 use serde::Deserialize;
 
-use crate::util::Result;
+use crate::{io::{PacketField, PacketReader, PacketWriter}, util::{ProtocolError, Result}};
 
-use super::{chunk::{Chunk, ChunkSection}, packed_array::PackedBitArray};
+use super::{block_ids, chunk::{Chunk, ChunkSection}, packed_array::PackedBitArray};
 
 #[derive(Debug, Deserialize)]
 pub struct MapTemplate {
@@ -20,7 +20,7 @@ impl MapTemplate {
 
         for chunk in self.chunks {
             let (x, y, z) = chunk.pos;
-            chunks.entry((x, z)).or_insert(Map::new()).insert(y, chunk.into());
+            chunks.entry((x, z)).or_default().insert(y, chunk.into());
         }
 
         let mut completed_chunks = Vec::with_capacity(chunks.len());
@@ -40,7 +40,8 @@ impl MapTemplate {
             });
         }
 
-        completed_chunks.sort_by_key(|c| i32::abs(c.x * 256 + c.z));
+        // Ties are broken by position so that loading the same map always gives the same order
+        completed_chunks.sort_by_key(|c| (i32::abs(c.x * 256 + c.z), c.x, c.z));
 
         completed_chunks
     }
@@ -63,20 +64,20 @@ pub struct TemplateChunk {
     pub pos: (i32, i32, i32),
 }
 
-impl Into<ChunkSection> for TemplateChunk {
-    fn into(self) -> ChunkSection {
-        let packed_states = PackedBitArray::new(self.block_states.data, self.block_states.palette.len());
+impl From<TemplateChunk> for ChunkSection {
+    fn from(chunk: TemplateChunk) -> Self {
+        let packed_states = PackedBitArray::new(chunk.block_states.data, chunk.block_states.palette.len());
 
         let mut block_states = Vec::new();
 
         for i in 0..4096 {
             let v = packed_states.get_value(i);
-            let state = self.block_states.palette.get(v as usize).unwrap();
+            let state = chunk.block_states.palette.get(v as usize).unwrap();
             block_states.push(state.clone());
         }
 
         ChunkSection {
-            y_pos: self.pos.1,
+            y_pos: chunk.pos.1,
             block_count: 4096,
             block_states,
         }
@@ -102,7 +103,7 @@ pub fn load_template(path: &Path) -> Result<MapTemplate> {
     Ok(template)
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct BlockEntity {
     pub id: String,
     pub x: i32,
@@ -111,3 +112,25 @@ pub struct BlockEntity {
     #[serde(flatten)]
     pub data: Map<String, Value>,
 }
+
+/// Block entities are written the way they appear in the block entity data packet
+impl PacketField for BlockEntity {
+    fn read_field<R: PacketReader>(rdr: &mut R) -> Result<Self> {
+        let (x, y, z) = rdr.read_position()?;
+        let type_id = rdr.read_var_int()?;
+        let id = block_ids::get_block_entity_name(type_id).ok_or(ProtocolError::InvalidEnumValue(type_id))?;
+        Ok(Self {
+            id: id.to_string(),
+            x,
+            y,
+            z,
+            data: rdr.read_nbt()?,
+        })
+    }
+
+    fn write_field<W: PacketWriter>(&self, wr: &mut W) -> Result<()> {
+        wr.write_position(self.x, self.y, self.z)?;
+        wr.write_var_int(block_ids::get_block_entity_id(&self.id).expect("invalid block entity ID"))?;
+        wr.write_nbt(&self.data)
+    }
+}
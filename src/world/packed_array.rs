@@ -21,6 +21,24 @@ impl PackedBitArray {
         }
     }
 
+    /// Wraps entries read from the network, returning `None` unless there's exactly enough data
+    /// for 4096 entries of the given size
+    pub fn from_raw(data: Vec<u64>, bits_per_entry: usize) -> Option<Self> {
+        if !(1..=64).contains(&bits_per_entry) {
+            return None;
+        }
+        let values_per_long = 64 / bits_per_entry;
+        if data.len() != 4096_usize.div_ceil(values_per_long) {
+            return None;
+        }
+
+        Some(Self {
+            data,
+            bits_per_entry,
+            values_per_long,
+        })
+    }
+
     pub fn empty(palette_size: usize) -> Self {
         let bits_per_entry = Self::compute_bits_per_entry(palette_size);
         let values_per_long = 64 / bits_per_entry;
@@ -66,7 +84,7 @@ impl PackedBitArray {
     }
 
     fn create_mask(&self, offset_in_long: usize) -> u64 {
-        let far_length = offset_in_long + self.bits_per_entry - 1;
+        let far_length = offset_in_long + self.bits_per_entry;
         mask(far_length) ^ mask(offset_in_long)
     }
 }
@@ -79,3 +97,48 @@ fn mask(length: usize) -> u64 {
         (1 << length) - 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_values() {
+        for bits_per_entry in [4, 5, 6, 7, 8, 9, 13, 15] {
+            let values_per_long = 64 / bits_per_entry;
+            let mut array = PackedBitArray::from_raw(vec![0; 4096_usize.div_ceil(values_per_long)], bits_per_entry).unwrap();
+            let value = |i: usize| (i as u64 * 7 + 3) & mask(bits_per_entry);
+            for i in 0..4096 {
+                array.put_value(i, value(i));
+            }
+            for i in 0..4096 {
+                assert_eq!(array.get_value(i), value(i), "bits per entry {}, index {}", bits_per_entry, i);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_values_next_to_long_boundaries() {
+        let mut array = PackedBitArray::empty(32);
+        // 5 bits per entry leaves 12 values per long, with 4 bits of padding at the top
+        for i in [10, 11, 12, 13] {
+            array.put_value(i, 31);
+        }
+        assert_eq!(array.data()[0], 0b11111_11111 << 50);
+        assert_eq!(array.data()[1], 0b11111_11111);
+        for i in [10, 11, 12, 13] {
+            assert_eq!(array.get_value(i), 31);
+        }
+        assert_eq!(array.get_value(9), 0);
+        assert_eq!(array.get_value(14), 0);
+    }
+
+    #[test]
+    fn from_raw_checks_length() {
+        assert!(PackedBitArray::from_raw(vec![0; 256], 4).is_some());
+        assert!(PackedBitArray::from_raw(vec![0; 3], 4).is_none());
+        assert!(PackedBitArray::from_raw(vec![0; 256], 5).is_none());
+        assert!(PackedBitArray::from_raw(vec![0; 4096], 0).is_none());
+        assert!(PackedBitArray::from_raw(vec![0; 4096], 65).is_none());
+    }
+}